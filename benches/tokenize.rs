@@ -0,0 +1,38 @@
+// Times the scanner on inputs of increasing size. The scanner walks the
+// source once without copying it, so the time per byte should stay flat
+// as the input grows.
+//
+// Run with `cargo bench --bench tokenize`.
+
+use std::time::{Duration, Instant};
+
+use mona::token::{new_source, scan};
+
+const UNIT: &str = "int fib(int n) {
+  /* a block comment */
+  if (n <= 1) return n; // a line comment
+  return fib(n - 1) + fib(n - 2);
+}
+char *s = \"hello\\n\";
+";
+
+const RUNS: usize = 5;
+
+fn main() {
+    println!("{:>12} {:>10} {:>8}", "bytes", "tokens", "ns/byte");
+    for n in [500, 1_000, 2_000, 4_000, 8_000, 16_000] {
+        let src = new_source(UNIT.repeat(n));
+
+        let mut best = Duration::MAX;
+        let mut count = 0;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let tokens = scan(src);
+            best = best.min(start.elapsed());
+            count = tokens.len();
+        }
+
+        let ns = best.as_nanos() as f64 / src.len() as f64;
+        println!("{:>12} {:>10} {:>8.2}", src.len(), count, ns);
+    }
+}
@@ -1,57 +1,246 @@
-use crate::ir::{IROp, IR};
-use crate::REGS;
+use crate::ir::{Function, Label, VReg, IR};
+use crate::sema::{Data, Global};
+use crate::{REGS, REGS16, REGS32, REGS8};
 
-pub fn gen_x86(irv: Vec<IR>) {
-    use IROp::*;
-    let ret = ".Lend";
+const ARGREGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+const ARGREGS8: [&str; 6] = ["dil", "sil", "dl", "cl", "r8b", "r9b"];
+const ARGREGS16: [&str; 6] = ["di", "si", "dx", "cx", "r8w", "r9w"];
+const ARGREGS32: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
 
+// Registers in REGS which a callee is free to clobber.
+const CALLER_SAVED: [&str; 4] = ["rdi", "rsi", "r10", "r11"];
+
+// Registers in REGS which we must preserve for our caller.
+const CALLEE_SAVED: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+
+fn emit_data(globals: &[Global]) {
+    for var in globals {
+        let data = match var.data {
+            Some(ref data) => data,
+            None => {
+                println!(".bss");
+                println!(".align {}", var.align);
+                println!("{}:", var.name);
+                println!("  .zero {}", var.size);
+                continue;
+            }
+        };
+
+        if var.readonly {
+            println!(".section .rodata");
+        } else {
+            println!(".data");
+        }
+        println!(".align {}", var.align);
+        println!("{}:", var.name);
+        for d in data {
+            match d {
+                Data::Bytes(bytes) => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+                    println!("  .byte {}", bytes.join(", "));
+                }
+                Data::Label(name) => println!("  .quad {}", name),
+            }
+        }
+    }
+}
+
+fn emit_call(lhs: VReg, name: &str, args: &[VReg]) {
+    for r in CALLER_SAVED.iter() {
+        println!("  push {}", r);
+    }
+
+    // Go through the stack so that an argument register which is also one
+    // of our own registers is not overwritten before it is read.
+    for arg in args.iter() {
+        println!("  push {}", REGS[arg.0]);
+    }
+    for r in ARGREGS[..args.len()].iter().rev() {
+        println!("  pop {}", r);
+    }
+
+    println!("  mov rax, 0");
+    println!("  call {}", name);
+
+    for r in CALLER_SAVED.iter().rev() {
+        println!("  pop {}", r);
+    }
+    println!("  mov {}, rax", REGS[lhs.0]);
+}
+
+// Sets the lhs register to 1 if the comparison holds, or to 0.
+fn emit_cmp(lhs: VReg, rhs: VReg, insn: &str) {
+    println!("  cmp {}, {}", REGS[lhs.0], REGS[rhs.0]);
+    println!("  {} {}", insn, REGS8[lhs.0]);
+    println!("  movzx {}, {}", REGS[lhs.0], REGS8[lhs.0]);
+}
+
+// The table holds 32-bit offsets from the table itself, so it needs no
+// relocations. An index out of range (including a negative one, compared
+// unsigned) goes to the default label.
+fn emit_jump_table(lhs: VReg, default: Label, targets: &[Label]) {
+    let r = REGS[lhs.0];
+    let table = format!(".L.jtab{}", default.0);
+
+    println!("  cmp {}, {}", r, targets.len());
+    println!("  jae {}", default);
+    println!("  lea rax, [rip+{}]", table);
+    println!("  movsxd {}, dword ptr [rax+{}*4]", r, r);
+    println!("  add rax, {}", r);
+    println!("  jmp rax");
+
+    println!(".section .rodata");
+    println!(".align 4");
+    println!("{}:", table);
+    for label in targets.iter() {
+        println!("  .long {}-{}", label, table);
+    }
+    println!(".text");
+}
+
+// Rewrites three-address arithmetic into the two-address form of x86 by
+// copying the left operand into the destination first. alloc_regs never
+// gives the destination the register of the right operand unless it is
+// also the left one, so the copy cannot clobber it.
+pub fn lower_two_address(irv: &mut Vec<IR>) {
+    let mut out = Vec::with_capacity(irv.len());
+    for mut ir in irv.drain(..) {
+        if let Some((dst, lhs, rhs)) = ir.arith_operands_mut() {
+            if *dst != *lhs {
+                assert!(rhs.is_none_or(|rhs| *rhs != *dst));
+                out.push(IR::Mov {
+                    lhs: *dst,
+                    rhs: *lhs,
+                });
+                *lhs = *dst;
+            }
+        }
+        out.push(ir);
+    }
+    *irv = out;
+}
+
+fn gen(f: Function) {
+    use IR::*;
+    let ret = format!(".Lreturn.{}", f.name);
+
+    println!(".global {}", f.name);
+    println!("{}:", f.name);
     println!("  push rbp");
     println!("  mov rbp, rsp");
 
-    for ir in irv {
-        let lhs = ir.lhs.unwrap();
-        match ir.op {
-            Imm => println!("  mov {}, {}", REGS[lhs], ir.rhs.unwrap()),
-            Mov => println!("  mov {}, {}", REGS[lhs], REGS[ir.rhs.unwrap()]),
-            Return => {
-                println!("  mov rax, {}", REGS[lhs]);
+    // Keep the stack 16-byte aligned.
+    println!("  sub rsp, {}", (CALLEE_SAVED.len() * 8).div_ceil(16) * 16);
+    for (i, r) in CALLEE_SAVED.iter().enumerate() {
+        println!("  mov [rbp-{}], {}", (i + 1) * 8, r);
+    }
+
+    for mut ir in f.irv {
+        // lower_two_address has made the destination of every arithmetic
+        // instruction its left operand, as x86 wants.
+        if let Some((dst, lhs, _)) = ir.arith_operands_mut() {
+            assert_eq!(dst, lhs, "arithmetic not lowered to two-address form");
+        }
+
+        match ir {
+            Imm { lhs, imm } => println!("  mov {}, {}", REGS[lhs.0], imm),
+            Mov { lhs, rhs } => println!("  mov {}, {}", REGS[lhs.0], REGS[rhs.0]),
+            Return { lhs } => {
+                println!("  mov rax, {}", REGS[lhs.0]);
                 println!("  jmp {}", ret);
             }
-            Label => println!(".L{}:", lhs),
-            Jmp => println!("  jmp .L{}", lhs),
-            Unless => {
-                println!("  cmp {}, 0", REGS[lhs]);
-                println!("  je .L{}", ir.rhs.unwrap());
+            Label { label } => println!("{}:", label),
+            Jmp { label } => println!("  jmp {}", label),
+            Unless { lhs, label } => {
+                println!("  cmp {}, 0", REGS[lhs.0]);
+                println!("  je {}", label);
             }
-            Alloca => {
-                if ir.rhs.is_some() {
-                    println!("  sub rsp, {}", ir.rhs.unwrap());
-                }
-                println!("  mov {}, rsp", REGS[lhs]);
-            }
-            Load => println!("  mov {}, [{}]", REGS[lhs], REGS[ir.rhs.unwrap()]),
-            Store => println!("  mov [{}], {}", REGS[lhs], REGS[ir.rhs.unwrap()]),
-            Add => println!(" add {}, {}", REGS[lhs], REGS[ir.rhs.unwrap()]),
-            AddImm => println!("  add {}, {}", REGS[lhs], ir.rhs.unwrap()),
-            Sub => println!("  sub {}, {}", REGS[lhs], REGS[ir.rhs.unwrap()]),
-            Mul => {
-                println!("  mov rax, {}", REGS[ir.rhs.unwrap()]);
-                println!("  mul {}", REGS[lhs]);
-                println!("  mov {}, rax", REGS[lhs]);
-            }
-            Div => {
-                println!("  mov rax, {}", REGS[lhs]);
+            JumpTable {
+                lhs,
+                default,
+                targets,
+            } => emit_jump_table(lhs, default, &targets),
+            Alloca { lhs, size } => {
+                println!("  sub rsp, {}", size);
+                println!("  mov {}, rsp", REGS[lhs.0]);
+            }
+            Load8 { lhs, rhs } => println!("  movsx {}, byte ptr [{}]", REGS[lhs.0], REGS[rhs.0]),
+            Load16 { lhs, rhs } => println!("  movsx {}, word ptr [{}]", REGS[lhs.0], REGS[rhs.0]),
+            Load32 { lhs, rhs } => {
+                println!("  movsxd {}, dword ptr [{}]", REGS[lhs.0], REGS[rhs.0])
+            }
+            Load64 { lhs, rhs } => println!("  mov {}, [{}]", REGS[lhs.0], REGS[rhs.0]),
+            Store8 { lhs, rhs } => println!("  mov [{}], {}", REGS[lhs.0], REGS8[rhs.0]),
+            Store16 { lhs, rhs } => println!("  mov [{}], {}", REGS[lhs.0], REGS16[rhs.0]),
+            Store32 { lhs, rhs } => println!("  mov [{}], {}", REGS[lhs.0], REGS32[rhs.0]),
+            Store64 { lhs, rhs } => println!("  mov [{}], {}", REGS[lhs.0], REGS[rhs.0]),
+            StoreArg8 { lhs, slot } => println!("  mov [{}], {}", REGS[lhs.0], ARGREGS8[slot.0]),
+            StoreArg16 { lhs, slot } => {
+                println!("  mov [{}], {}", REGS[lhs.0], ARGREGS16[slot.0])
+            }
+            StoreArg32 { lhs, slot } => {
+                println!("  mov [{}], {}", REGS[lhs.0], ARGREGS32[slot.0])
+            }
+            StoreArg64 { lhs, slot } => println!("  mov [{}], {}", REGS[lhs.0], ARGREGS[slot.0]),
+            Sext8 { lhs } => println!("  movsx {}, {}", REGS[lhs.0], REGS8[lhs.0]),
+            Sext16 { lhs } => println!("  movsx {}, {}", REGS[lhs.0], REGS16[lhs.0]),
+            Sext32 { lhs } => println!("  movsxd {}, {}", REGS[lhs.0], REGS32[lhs.0]),
+            Add { dst: lhs, rhs, .. } => println!("  add {}, {}", REGS[lhs.0], REGS[rhs.0]),
+            AddImm { dst: lhs, imm, .. } => println!("  add {}, {}", REGS[lhs.0], imm),
+            Sub { dst: lhs, rhs, .. } => println!("  sub {}, {}", REGS[lhs.0], REGS[rhs.0]),
+            Mul { dst: lhs, rhs, .. } => {
+                println!("  mov rax, {}", REGS[rhs.0]);
+                println!("  mul {}", REGS[lhs.0]);
+                println!("  mov {}, rax", REGS[lhs.0]);
+            }
+            Div { dst: lhs, rhs, .. } => {
+                println!("  mov rax, {}", REGS[lhs.0]);
                 println!("  cqo");
-                println!("  div {}", REGS[ir.rhs.unwrap()]);
-                println!("  mov {}, rax", REGS[lhs]);
+                println!("  idiv {}", REGS[rhs.0]);
+                println!("  mov {}, rax", REGS[lhs.0]);
+            }
+            Mod { dst: lhs, rhs, .. } => {
+                println!("  mov rax, {}", REGS[lhs.0]);
+                println!("  cqo");
+                println!("  idiv {}", REGS[rhs.0]);
+                println!("  mov {}, rdx", REGS[lhs.0]);
+            }
+            Shl { dst: lhs, rhs, .. } => {
+                println!("  mov rcx, {}", REGS[rhs.0]);
+                println!("  shl {}, cl", REGS[lhs.0]);
+            }
+            Shr { dst: lhs, rhs, .. } => {
+                println!("  mov rcx, {}", REGS[rhs.0]);
+                println!("  sar {}, cl", REGS[lhs.0]);
             }
-            Nop | Kill => (),
+            And { dst: lhs, rhs, .. } => println!("  and {}, {}", REGS[lhs.0], REGS[rhs.0]),
+            Or { dst: lhs, rhs, .. } => println!("  or {}, {}", REGS[lhs.0], REGS[rhs.0]),
+            Xor { dst: lhs, rhs, .. } => println!("  xor {}, {}", REGS[lhs.0], REGS[rhs.0]),
+            Eq { dst: lhs, rhs, .. } => emit_cmp(lhs, rhs, "sete"),
+            Ne { dst: lhs, rhs, .. } => emit_cmp(lhs, rhs, "setne"),
+            Lt { dst: lhs, rhs, .. } => emit_cmp(lhs, rhs, "setl"),
+            Le { dst: lhs, rhs, .. } => emit_cmp(lhs, rhs, "setle"),
+            LabelAddr { lhs, name } => println!("  lea {}, [rip+{}]", REGS[lhs.0], name),
+            Call { lhs, name, args } => emit_call(lhs, &name, &args),
+            Nop | Kill { .. } => (),
         }
     }
 
     println!("{}:", ret);
-    println!("  mov rsp, rbp");
+    for (i, r) in CALLEE_SAVED.iter().enumerate() {
+        println!("  mov {}, [rbp-{}]", r, (i + 1) * 8);
+    }
     println!("  mov rsp, rbp");
     println!("  pop rbp");
     println!("  ret");
 }
+
+pub fn gen_x86(globals: &[Global], fns: Vec<Function>) {
+    println!(".intel_syntax noprefix");
+    emit_data(globals);
+
+    println!(".text");
+    for f in fns {
+        gen(f);
+    }
+}
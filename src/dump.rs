@@ -0,0 +1,185 @@
+use crate::parse::{Node, NodeType};
+
+// A node broken down into what both dumps print: its kind, its scalar
+// fields and its children, in source order.
+struct Fields<'a> {
+    kind: &'static str,
+    attrs: Vec<(&'static str, Attr)>,
+    children: Vec<&'a Node>,
+}
+
+enum Attr {
+    Int(i64),
+    Str(String),
+    // The contents of a string literal, which is quoted in the tree
+    Text(String),
+}
+
+impl Attr {
+    fn str(s: impl ToString) -> Self {
+        Attr::Str(s.to_string())
+    }
+}
+
+fn fields(node: &Node) -> Fields<'_> {
+    use NodeType::*;
+
+    let (kind, attrs, children): (_, _, Vec<&Node>) = match node.ty {
+        Num(val) => ("Num", vec![("value", Attr::Int(val))], vec![]),
+        Str(ref bytes) => {
+            let s = String::from_utf8_lossy(bytes);
+            ("Str", vec![("value", Attr::Text(s.into_owned()))], vec![])
+        }
+        Ident(ref name) => ("Ident", vec![("name", Attr::str(name))], vec![]),
+        Gvar(ref name) => ("Gvar", vec![("name", Attr::str(name))], vec![]),
+        Lvar(offset) => ("Lvar", vec![("offset", Attr::Int(offset as i64))], vec![]),
+        Call(ref name, ref args) => (
+            "Call",
+            vec![("name", Attr::str(name))],
+            args.iter().collect(),
+        ),
+        Vardef(ref name, ref init) => (
+            "Vardef",
+            vec![("name", Attr::str(name)), ("type", Attr::str(&node.ctype))],
+            init.iter().map(|n| &**n).collect(),
+        ),
+        Cast(ref expr) => ("Cast", vec![("type", Attr::str(&node.ctype))], vec![expr]),
+        Addr(ref expr) => ("Addr", vec![], vec![expr]),
+        Deref(ref expr) => ("Deref", vec![], vec![expr]),
+        PostInc(ref expr) => ("PostInc", vec![], vec![expr]),
+        PostDec(ref expr) => ("PostDec", vec![], vec![expr]),
+        PreInc(ref expr) => ("PreInc", vec![], vec![expr]),
+        PreDec(ref expr) => ("PreDec", vec![], vec![expr]),
+        Unary(ref op, ref expr) => ("Unary", vec![("op", Attr::str(op))], vec![expr]),
+        Sizeof(ref expr) => ("Sizeof", vec![], vec![expr]),
+        SizeofType(ref ty) => ("SizeofType", vec![("type", Attr::str(ty))], vec![]),
+        EnumConst(ref name, val) => {
+            let attrs = vec![("name", Attr::str(name)), ("value", Attr::Int(val))];
+            ("EnumConst", attrs, vec![])
+        }
+        Index(ref expr, ref index) => ("Index", vec![], vec![expr, index]),
+        Dot(ref expr, ref name) => ("Dot", vec![("name", Attr::str(name))], vec![expr]),
+        Arrow(ref expr, ref name) => ("Arrow", vec![("name", Attr::str(name))], vec![expr]),
+        BinOp(ref op, ref lhs, ref rhs) => ("BinOp", vec![("op", Attr::str(op))], vec![lhs, rhs]),
+        Ternary(ref cond, ref then, ref els) => ("Ternary", vec![], vec![cond, then, els]),
+        If(ref cond, ref then, ref els) => {
+            let mut children = vec![&**cond, then];
+            children.extend(els.iter().map(|n| &**n));
+            ("If", vec![], children)
+        }
+        Switch(ref cond, ref body, _, _) => ("Switch", vec![], vec![cond, body]),
+        Case(ref expr, val, ref body) => {
+            ("Case", vec![("value", Attr::Int(val))], vec![expr, body])
+        }
+        Default(ref body) => ("Default", vec![], vec![body]),
+        Break => ("Break", vec![], vec![]),
+        Null => ("Null", vec![], vec![]),
+        Label(ref name, ref body) => ("Label", vec![("name", Attr::str(name))], vec![body]),
+        Goto(ref name) => ("Goto", vec![("name", Attr::str(name))], vec![]),
+        Return(ref expr) => ("Return", vec![], vec![expr]),
+        ExprStmt(ref expr) => ("ExprStmt", vec![], vec![expr]),
+        CompStmt(ref stmts) => ("CompStmt", vec![], stmts.iter().collect()),
+        InitList(ref elems) => ("InitList", vec![], elems.iter().collect()),
+        Typedef(ref name) => (
+            "Typedef",
+            vec![("name", Attr::str(name)), ("type", Attr::str(&node.ctype))],
+            vec![],
+        ),
+        TypeDecl => ("TypeDecl", vec![("type", Attr::str(&node.ctype))], vec![]),
+        Func(ref name, ref params, ref body) => {
+            let mut children: Vec<&Node> = params.iter().collect();
+            children.push(body);
+            let attrs = vec![("name", Attr::str(name)), ("type", Attr::str(&node.ctype))];
+            ("Func", attrs, children)
+        }
+    };
+    Fields {
+        kind,
+        attrs,
+        children,
+    }
+}
+
+fn write_tree(node: &Node, depth: usize, spans: bool, out: &mut String) {
+    let f = fields(node);
+    *out += &format!("{}{}", "  ".repeat(depth), f.kind);
+    for (_, attr) in f.attrs.iter() {
+        match attr {
+            Attr::Int(val) => *out += &format!(" {}", val),
+            Attr::Str(s) => *out += &format!(" {}", s),
+            Attr::Text(s) => *out += &format!(" {:?}", s),
+        }
+    }
+    match node.span {
+        Some(span) if spans => *out += &format!(" [{}..{}]", span.start, span.end),
+        _ => (),
+    }
+    out.push('\n');
+
+    for child in f.children {
+        write_tree(child, depth + 1, spans, out);
+    }
+}
+
+// Prints the nodes as an indented tree, one node per line with its byte
+// range in the source.
+pub fn dump_ast(nodes: &[Node]) {
+    let mut out = String::new();
+    for node in nodes {
+        write_tree(node, 0, true, &mut out);
+    }
+    print!("{}", out);
+}
+
+// The tree dump without byte ranges, which is the same for two programs
+// that only differ in layout.
+pub fn ast_shape(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        write_tree(node, 0, false, &mut out);
+    }
+    out
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn to_json(node: &Node) -> String {
+    let f = fields(node);
+    let mut out = format!("{{\"kind\":{}", json_str(f.kind));
+    for (key, attr) in f.attrs.iter() {
+        let val = match attr {
+            Attr::Int(val) => val.to_string(),
+            Attr::Str(s) | Attr::Text(s) => json_str(s),
+        };
+        out += &format!(",{}:{}", json_str(key), val);
+    }
+    match node.span {
+        Some(span) => out += &format!(",\"span\":[{},{}]", span.start, span.end),
+        None => out += ",\"span\":null",
+    }
+    let children: Vec<String> = f.children.into_iter().map(to_json).collect();
+    out += &format!(",\"children\":[{}]}}", children.join(","));
+    out
+}
+
+// Prints the nodes as a JSON array. Every node is an object with its
+// "kind", the fields of that kind, a "span" of [start, end] byte offsets
+// or null, and its "children".
+pub fn dump_ast_json(nodes: &[Node]) {
+    let nodes: Vec<String> = nodes.iter().map(to_json).collect();
+    println!("[{}]", nodes.join(","));
+}
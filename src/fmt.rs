@@ -0,0 +1,738 @@
+use crate::dump::ast_shape;
+use crate::parse::{
+    binary_op, Assoc, Attach, DeclSyntax, Node, NodeType, TypeSpec, ASSIGN_LEVEL, COMMA_LEVEL,
+    CONDITIONAL_LEVEL,
+};
+use crate::token::{new_source, push_eof, scan_with_comments, Comment, Span, Token, TokenType};
+
+const INDENT: &str = "  ";
+
+// Precedence levels of the operators parsed outside of the BINARY_OPS table
+const PRIMARY_LEVEL: u8 = 0;
+const POSTFIX_LEVEL: u8 = 1;
+const UNARY_LEVEL: u8 = 2;
+
+// Reprints a program in the canonical layout: two-space indentation,
+// braces on the line of their statement, spaces around binary operators
+// and only the parentheses which precedence requires. Comments and
+// preprocessor directives are kept.
+//
+// The output is formatted once more to check that it parses into the same
+// AST and that formatting it again changes nothing.
+pub fn format(src: String) -> String {
+    let (out, shape) = format_once(src);
+    let (again, new_shape) = format_once(out.clone());
+    if new_shape != shape {
+        panic!("formatting changed the meaning of the program");
+    }
+    if again != out {
+        panic!("formatting is not idempotent");
+    }
+    out
+}
+
+// Returns the formatted program and the shape of its AST.
+fn format_once(src: String) -> (String, String) {
+    let src = new_source(src);
+    let (tokens, comments) = scan_with_comments(src);
+    let (mut tokens, comments) = take_directives(src, tokens, comments);
+    push_eof(&mut tokens);
+
+    let mut nodes = Node::parse(&tokens);
+    let shape = ast_shape(&nodes);
+
+    let mut attacher = Attacher {
+        newlines: src.match_indices('\n').map(|(i, _)| i).collect(),
+        pool: comments.into_iter().map(Some).collect(),
+    };
+    attacher.list(&mut nodes, src.len());
+    let rest = take(&mut attacher.pool, |_| true);
+
+    let mut p = Printer {
+        src,
+        out: String::new(),
+        indent: 0,
+        bol: true,
+        last: None,
+    };
+    p.toplevel(&nodes, &rest);
+    (p.out, shape)
+}
+
+// The formatter doesn't expand macros, so directives are taken out of the
+// token stream and kept verbatim like comments. A directive runs to the end
+// of its line, including continuation lines and comments started on it.
+fn take_directives(
+    src: &'static str,
+    tokens: Vec<Token>,
+    mut comments: Vec<Comment>,
+) -> (Vec<Token>, Vec<Comment>) {
+    let mut lines: Vec<Span> = vec![];
+    for t in tokens.iter() {
+        if !t.bol || !matches!(t.ty, TokenType::Hash) {
+            continue;
+        }
+        if lines.last().is_some_and(|l| t.span.start < l.end) {
+            continue;
+        }
+
+        let start = t.span.start;
+        let mut end = line_end(src, start);
+        while let Some(c) = comments
+            .iter()
+            .find(|c| start <= c.span.start && c.span.start < end && c.span.end > end)
+        {
+            end = line_end(src, c.span.end);
+        }
+        lines.push(Span { start, end });
+    }
+
+    let inside = |span: Span| {
+        lines
+            .iter()
+            .any(|l| l.start <= span.start && span.start < l.end)
+    };
+    let tokens = tokens.into_iter().filter(|t| !inside(t.span)).collect();
+    comments.retain(|c| !inside(c.span));
+    for l in lines.iter() {
+        let text = src[l.start..l.end].trim_end();
+        comments.push(Comment {
+            text,
+            span: Span {
+                start: l.start,
+                end: l.start + text.len(),
+            },
+            directive: true,
+        });
+    }
+    comments.sort_by_key(|c| c.span.start);
+    (tokens, comments)
+}
+
+// The end of the line containing `pos`, where a backslash before the
+// newline continues the line.
+fn line_end(src: &str, pos: usize) -> usize {
+    let mut end = pos;
+    loop {
+        match src[end..].find('\n') {
+            Some(i) if src[..end + i].ends_with('\\') => end += i + 1,
+            Some(i) => return end + i,
+            None => return src.len(),
+        }
+    }
+}
+
+fn span(node: &Node) -> Span {
+    node.span.expect("statement without a source span")
+}
+
+// Removes the comments matching `pred` from the pool, in source order.
+fn take(pool: &mut [Option<Comment>], pred: impl Fn(&Comment) -> bool) -> Vec<Comment> {
+    pool.iter_mut()
+        .filter(|c| c.as_ref().is_some_and(&pred))
+        .map(|c| c.take().unwrap())
+        .collect()
+}
+
+// Whether comments left inside the node go before its closing brace.
+fn is_container(node: &Node) -> bool {
+    let has_body = match node.syntax.as_ref().map(|s| &s.spec) {
+        Some(TypeSpec::Struct(_, _, members)) => members.is_some(),
+        Some(TypeSpec::Enum(_, items)) => items.is_some(),
+        _ => false,
+    };
+    match node.ty {
+        NodeType::CompStmt(_) => true,
+        NodeType::Vardef(..) | NodeType::Typedef(_) | NodeType::TypeDecl => has_body,
+        _ => false,
+    }
+}
+
+// Hands out comments to statements, declarations and struct members,
+// visiting them in source order:
+//
+//  - A comment after a node on the line where it ends trails it. Outer
+//    nodes take theirs before inner ones.
+//  - Other comments lead the first node after them.
+//  - Comments left inside a block go before its closing brace, and those
+//    left inside any other node lead it.
+struct Attacher {
+    newlines: Vec<usize>,
+    pool: Vec<Option<Comment>>,
+}
+
+impl Attacher {
+    fn line(newlines: &[usize], pos: usize) -> usize {
+        newlines.partition_point(|&n| n < pos)
+    }
+
+    // Visits a list of nodes, each of which may take trailing comments up
+    // to where the next one starts.
+    fn list(&mut self, nodes: &mut [Node], limit: usize) {
+        let starts: Vec<usize> = nodes.iter().map(|n| span(n).start).collect();
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let limit = starts.get(i + 1).copied().unwrap_or(limit);
+            self.slot(node, limit);
+        }
+    }
+
+    fn slot(&mut self, node: &mut Node, limit: usize) {
+        let span = span(node);
+        let leading = take(&mut self.pool, |c| c.span.start < span.start);
+        let line = Self::line(&self.newlines, span.end);
+        let newlines = &self.newlines;
+        let trailing = take(&mut self.pool, |c| {
+            c.span.start >= span.end
+                && c.span.start < limit
+                && Self::line(newlines, c.span.start) == line
+        });
+
+        self.children(node, span.end);
+        let inner = take(&mut self.pool, |c| c.span.start < span.end);
+        let kind = if is_container(node) {
+            Attach::Closing
+        } else {
+            Attach::Leading
+        };
+
+        let comments = &mut node.comments;
+        comments.extend(leading.into_iter().map(|c| (Attach::Leading, c)));
+        comments.extend(inner.into_iter().map(|c| (kind, c)));
+        comments.extend(trailing.into_iter().map(|c| (Attach::Trailing, c)));
+    }
+
+    // The body of a statement or function. A block there only takes the
+    // comments inside it.
+    fn branch(&mut self, node: &mut Node, limit: usize) {
+        if !matches!(node.ty, NodeType::CompStmt(_)) {
+            return self.slot(node, limit);
+        }
+        let end = span(node).end;
+        self.children(node, end);
+        let inner = take(&mut self.pool, |c| c.span.start < end);
+        node.comments
+            .extend(inner.into_iter().map(|c| (Attach::Closing, c)));
+    }
+
+    fn children(&mut self, node: &mut Node, limit: usize) {
+        if let Some(ref mut syntax) = node.syntax {
+            if let TypeSpec::Struct(_, _, Some(ref mut members)) = syntax.spec {
+                self.list(members, limit);
+            }
+        }
+
+        match node.ty {
+            NodeType::CompStmt(ref mut stmts) => self.list(stmts, limit),
+            NodeType::If(_, ref mut then, ref mut els) => {
+                let then_limit = els.as_ref().map_or(limit, |els| span(els).start);
+                self.branch(then, then_limit);
+                if let Some(els) = els {
+                    self.branch(els, limit);
+                }
+            }
+            NodeType::Switch(_, ref mut body, ..)
+            | NodeType::Case(_, _, ref mut body)
+            | NodeType::Default(ref mut body)
+            | NodeType::Label(_, ref mut body)
+            | NodeType::Func(_, _, ref mut body) => self.branch(body, limit),
+            _ => (),
+        }
+    }
+}
+
+fn comments(node: &Node, kind: Attach) -> Vec<&Comment> {
+    node.comments
+        .iter()
+        .filter(|(k, _)| *k == kind)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+// A string literal spelled with the simplest escapes.
+fn quote(bytes: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => s += "\\\"",
+            b'\\' => s += "\\\\",
+            b'\n' => s += "\\n",
+            b'\t' => s += "\\t",
+            0x20..=0x7e => s.push(b as char),
+            _ => s += &format!("\\{:03o}", b),
+        }
+    }
+    s.push('"');
+    s
+}
+
+struct Printer {
+    src: &'static str,
+    out: String,
+    indent: usize,
+    // Whether the next write starts a new line
+    bol: bool,
+    // Where in the source the last thing printed ended, to find blank
+    // lines to keep. None at the start of a block.
+    last: Option<usize>,
+}
+
+impl Printer {
+    fn write(&mut self, s: &str) {
+        if self.bol {
+            self.out += &INDENT.repeat(self.indent);
+            self.bol = false;
+        }
+        self.out += s;
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.bol = true;
+    }
+
+    fn blank_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    // Keeps a blank line which separates something starting at `start`
+    // from what was printed before it.
+    fn gap(&mut self, start: usize) {
+        if let Some(last) = self.last {
+            let text = self.src.get(last..start).unwrap_or("");
+            if text.trim().is_empty() && text.matches('\n').count() > 1 {
+                self.blank_line();
+            }
+        }
+    }
+
+    // A comment on a line of its own. Directives always start in column 0.
+    fn comment(&mut self, c: &Comment) {
+        self.gap(c.span.start);
+        if c.directive {
+            self.out += c.text;
+        } else {
+            self.write(c.text);
+        }
+        self.newline();
+        self.last = Some(c.span.end);
+    }
+
+    // Comments appended to the line printed last.
+    fn trailing(&mut self, comments: Vec<&Comment>) {
+        if comments.is_empty() {
+            return;
+        }
+        if self.bol {
+            self.out.pop();
+            self.bol = false;
+        }
+        for c in comments {
+            self.out += " ";
+            self.out += c.text;
+            self.last = Some(c.span.end);
+        }
+        self.newline();
+    }
+
+    fn toplevel(&mut self, nodes: &[Node], rest: &[Comment]) {
+        let is_func = |node: &Node| matches!(node.ty, NodeType::Func(..));
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 && (is_func(node) || is_func(&nodes[i - 1])) {
+                self.blank_line();
+            }
+            self.stmt(node);
+        }
+        for c in rest {
+            self.comment(c);
+        }
+    }
+
+    // Statements of a block, then the comments before its closing brace.
+    fn items(&mut self, nodes: &[Node], closing: Vec<&Comment>) {
+        self.last = None;
+        for node in nodes {
+            self.stmt(node);
+        }
+        for c in closing {
+            self.comment(c);
+        }
+    }
+
+    fn block(&mut self, node: &Node) {
+        let stmts = match node.ty {
+            NodeType::CompStmt(ref stmts) => stmts,
+            _ => unreachable!(),
+        };
+        self.write("{");
+        self.newline();
+        self.indent += 1;
+        self.items(stmts, comments(node, Attach::Closing));
+        self.indent -= 1;
+        self.write("}");
+    }
+
+    fn stmt(&mut self, node: &Node) {
+        for c in comments(node, Attach::Leading) {
+            self.comment(c);
+        }
+        let span = span(node);
+        self.gap(span.start);
+        self.stmt_body(node);
+        if !self.bol {
+            self.newline();
+        }
+        self.last = Some(span.end);
+        self.trailing(comments(node, Attach::Trailing));
+    }
+
+    // A statement's own text. It ends either at the start of a line, or
+    // right after its closing `;` or `}`.
+    fn stmt_body(&mut self, node: &Node) {
+        match node.ty {
+            NodeType::ExprStmt(ref expr) => {
+                let s = self.expr(expr, COMMA_LEVEL);
+                self.write(&format!("{};", s));
+            }
+            NodeType::Return(ref expr) => {
+                let s = self.expr(expr, COMMA_LEVEL);
+                self.write(&format!("return {};", s));
+            }
+            NodeType::Break => self.write("break;"),
+            NodeType::Null => self.write(";"),
+            NodeType::Goto(ref name) => self.write(&format!("goto {};", name)),
+            NodeType::Vardef(ref name, ref init) => {
+                self.decl(node, name);
+                if let Some(init) = init {
+                    let s = self.initializer(init);
+                    self.write(&format!(" = {}", s));
+                }
+                self.write(";");
+            }
+            NodeType::Typedef(ref name) => {
+                self.write("typedef ");
+                self.decl(node, name);
+                self.write(";");
+            }
+            NodeType::TypeDecl => {
+                self.spec(node);
+                self.write(";");
+            }
+            NodeType::CompStmt(_) => self.block(node),
+            NodeType::If(ref cond, ref then, ref els) => {
+                let s = self.expr(cond, COMMA_LEVEL);
+                self.write(&format!("if ({})", s));
+                self.branch(then);
+                if let Some(els) = els {
+                    self.write(if self.bol { "else" } else { " else" });
+                    if matches!(els.ty, NodeType::If(..)) && els.comments.is_empty() {
+                        self.write(" ");
+                        self.stmt_body(els);
+                    } else {
+                        self.branch(els);
+                    }
+                }
+            }
+            NodeType::Switch(ref cond, ref body, ..) => {
+                let s = self.expr(cond, COMMA_LEVEL);
+                self.write(&format!("switch ({})", s));
+                self.branch(body);
+            }
+            NodeType::Case(ref expr, _, ref body) => {
+                let s = self.expr(expr, CONDITIONAL_LEVEL);
+                self.label(&format!("case {}:", s));
+                self.stmt(body);
+            }
+            NodeType::Default(ref body) => {
+                self.label("default:");
+                self.stmt(body);
+            }
+            NodeType::Label(ref name, ref body) => {
+                self.label(&format!("{}:", name));
+                self.stmt(body);
+            }
+            NodeType::Func(ref name, ref params, ref body) => {
+                let syntax = node.syntax.as_ref().unwrap();
+                self.spec(node);
+                let params: Vec<String> = params.iter().map(|p| self.param(p)).collect();
+                let stars = "*".repeat(syntax.ptrs);
+                self.write(&format!(" {}{}({}) ", stars, name, params.join(", ")));
+                self.block(body);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Labels stand out one level to the left of the statements around them.
+    fn label(&mut self, s: &str) {
+        let indent = self.indent;
+        self.indent = indent.saturating_sub(1);
+        self.write(s);
+        self.newline();
+        self.indent = indent;
+    }
+
+    // The body of if, switch and the like: a block on the same line, or
+    // any other statement indented on the next one.
+    fn branch(&mut self, node: &Node) {
+        if matches!(node.ty, NodeType::CompStmt(_)) {
+            self.write(" ");
+            return self.block(node);
+        }
+        self.newline();
+        self.indent += 1;
+        self.stmt(node);
+        self.indent -= 1;
+    }
+
+    // The type specifier of a declaration, with the members of a struct or
+    // enum body on lines of their own.
+    fn spec(&mut self, node: &Node) {
+        let syntax = node.syntax.as_ref().unwrap();
+        match syntax.spec {
+            TypeSpec::Struct(is_union, ref tag, Some(ref members)) => {
+                self.write(&struct_head(is_union, tag));
+                self.write(" {");
+                self.newline();
+                self.indent += 1;
+                self.items(members, comments(node, Attach::Closing));
+                self.indent -= 1;
+                self.write("}");
+            }
+            TypeSpec::Enum(ref tag, Some(ref items)) => {
+                self.write(&enum_head(tag));
+                self.write(" {");
+                self.newline();
+                self.indent += 1;
+                for (i, (name, val)) in items.iter().enumerate() {
+                    self.write(name);
+                    if let Some(val) = val {
+                        let s = self.expr(val, CONDITIONAL_LEVEL);
+                        self.write(&format!(" = {}", s));
+                    }
+                    if i + 1 < items.len() {
+                        self.write(",");
+                    }
+                    self.newline();
+                }
+                self.last = None;
+                for c in comments(node, Attach::Closing) {
+                    self.comment(c);
+                }
+                self.indent -= 1;
+                self.write("}");
+            }
+            ref spec => {
+                let s = self.spec_inline(spec);
+                self.write(&s);
+            }
+        }
+    }
+
+    fn decl(&mut self, node: &Node, name: &str) {
+        self.spec(node);
+        let syntax = node.syntax.as_ref().unwrap();
+        let s = self.declarator(syntax, name);
+        self.write(&format!(" {}", s));
+    }
+
+    fn declarator(&self, syntax: &DeclSyntax, name: &str) -> String {
+        let mut s = "*".repeat(syntax.ptrs) + name;
+        for dim in syntax.dims.iter() {
+            s += &format!("[{}]", self.expr(dim, CONDITIONAL_LEVEL));
+        }
+        s
+    }
+
+    // A type specifier on one line, for parameters and type names.
+    fn spec_inline(&self, spec: &TypeSpec) -> String {
+        match spec {
+            TypeSpec::Char => "char".to_string(),
+            TypeSpec::Short => "short".to_string(),
+            TypeSpec::Int => "int".to_string(),
+            TypeSpec::Long => "long".to_string(),
+            TypeSpec::Typedef(name) => name.clone(),
+            TypeSpec::Struct(is_union, tag, None) => struct_head(*is_union, tag),
+            TypeSpec::Struct(is_union, tag, Some(members)) => {
+                let mut s = struct_head(*is_union, tag) + " {";
+                for m in members {
+                    s += &format!(" {};", self.param(m));
+                }
+                s + " }"
+            }
+            TypeSpec::Enum(tag, None) => enum_head(tag),
+            TypeSpec::Enum(tag, Some(items)) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|(name, val)| match val {
+                        Some(val) => format!("{} = {}", name, self.expr(val, CONDITIONAL_LEVEL)),
+                        None => name.clone(),
+                    })
+                    .collect();
+                format!("{} {{ {} }}", enum_head(tag), items.join(", "))
+            }
+        }
+    }
+
+    // A declaration without an initializer on one line
+    fn param(&self, node: &Node) -> String {
+        let syntax = node.syntax.as_ref().unwrap();
+        let name = match node.ty {
+            NodeType::Vardef(ref name, _) => name,
+            _ => unreachable!(),
+        };
+        let spec = self.spec_inline(&syntax.spec);
+        format!("{} {}", spec, self.declarator(syntax, name))
+    }
+
+    // A type name as in sizeof(int *).
+    fn type_name(&self, syntax: &DeclSyntax) -> String {
+        let mut s = self.spec_inline(&syntax.spec);
+        if syntax.ptrs > 0 {
+            s += " ";
+        }
+        s + &self.declarator(syntax, "")
+    }
+
+    fn initializer(&self, node: &Node) -> String {
+        match node.ty {
+            NodeType::InitList(ref elems) => {
+                let elems: Vec<String> = elems.iter().map(|e| self.initializer(e)).collect();
+                format!("{{{}}}", elems.join(", "))
+            }
+            _ => self.expr(node, ASSIGN_LEVEL),
+        }
+    }
+
+    // An expression, parenthesized if it binds looser than `level`.
+    fn expr(&self, node: &Node, level: u8) -> String {
+        let (node_level, s) = self.expr_inner(node);
+        if node_level > level {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+
+    fn expr_inner(&self, node: &Node) -> (u8, String) {
+        match node.ty {
+            NodeType::Num(val) => {
+                let s = self.literal(node).unwrap_or_else(|| val.to_string());
+                (PRIMARY_LEVEL, s)
+            }
+            NodeType::Str(ref bytes) => {
+                let s = self.literal(node).unwrap_or_else(|| quote(bytes));
+                (PRIMARY_LEVEL, s)
+            }
+            NodeType::Ident(ref name) | NodeType::EnumConst(ref name, _) => {
+                (PRIMARY_LEVEL, name.clone())
+            }
+            NodeType::Call(ref name, ref args) => {
+                let args: Vec<String> = args.iter().map(|a| self.expr(a, ASSIGN_LEVEL)).collect();
+                (POSTFIX_LEVEL, format!("{}({})", name, args.join(", ")))
+            }
+            NodeType::Index(ref expr, ref index) => {
+                let s = format!(
+                    "{}[{}]",
+                    self.expr(expr, POSTFIX_LEVEL),
+                    self.expr(index, COMMA_LEVEL)
+                );
+                (POSTFIX_LEVEL, s)
+            }
+            NodeType::Dot(ref expr, ref name) => {
+                let s = format!("{}.{}", self.expr(expr, POSTFIX_LEVEL), name);
+                (POSTFIX_LEVEL, s)
+            }
+            NodeType::Arrow(ref expr, ref name) => {
+                let s = format!("{}->{}", self.expr(expr, POSTFIX_LEVEL), name);
+                (POSTFIX_LEVEL, s)
+            }
+            NodeType::PostInc(ref expr) => (
+                POSTFIX_LEVEL,
+                format!("{}++", self.expr(expr, POSTFIX_LEVEL)),
+            ),
+            NodeType::PostDec(ref expr) => (
+                POSTFIX_LEVEL,
+                format!("{}--", self.expr(expr, POSTFIX_LEVEL)),
+            ),
+            NodeType::Deref(ref expr) => (UNARY_LEVEL, self.prefix("*", expr)),
+            NodeType::Addr(ref expr) => (UNARY_LEVEL, self.prefix("&", expr)),
+            NodeType::PreInc(ref expr) => (UNARY_LEVEL, self.prefix("++", expr)),
+            NodeType::PreDec(ref expr) => (UNARY_LEVEL, self.prefix("--", expr)),
+            NodeType::Unary(ref op, ref expr) => (UNARY_LEVEL, self.prefix(&op.to_string(), expr)),
+            NodeType::Sizeof(ref expr) => (
+                UNARY_LEVEL,
+                format!("sizeof({})", self.expr(expr, COMMA_LEVEL)),
+            ),
+            NodeType::SizeofType(_) => {
+                let syntax = node.syntax.as_ref().unwrap();
+                (UNARY_LEVEL, format!("sizeof({})", self.type_name(syntax)))
+            }
+            NodeType::BinOp(ref op, ref lhs, ref rhs) => {
+                let (level, assoc) = binary_op(op).unwrap();
+                let (lhs_level, rhs_level) = match assoc {
+                    Assoc::Left => (level, level - 1),
+                    Assoc::Right => (level - 1, level),
+                };
+                let lhs = self.expr(lhs, lhs_level);
+                let rhs = self.expr(rhs, rhs_level);
+                let s = match op {
+                    TokenType::Comma => format!("{}, {}", lhs, rhs),
+                    _ => format!("{} {} {}", lhs, op, rhs),
+                };
+                (level, s)
+            }
+            NodeType::Ternary(ref cond, ref then, ref els) => {
+                let s = format!(
+                    "{} ? {} : {}",
+                    self.expr(cond, CONDITIONAL_LEVEL - 1),
+                    self.expr(then, COMMA_LEVEL),
+                    self.expr(els, CONDITIONAL_LEVEL)
+                );
+                (CONDITIONAL_LEVEL, s)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // A prefix operator and its operand, which are kept apart where they
+    // would otherwise read as another token, as in `- -x` or `& &x`.
+    fn prefix(&self, op: &str, expr: &Node) -> String {
+        let s = self.expr(expr, UNARY_LEVEL);
+        let last = op.chars().last().unwrap();
+        if matches!(last, '+' | '-' | '&') && s.starts_with(last) {
+            format!("{} {}", op, s)
+        } else {
+            format!("{}{}", op, s)
+        }
+    }
+
+    // A literal as written, like 0x1f or 'a'. The span of a parenthesized
+    // literal covers the parentheses, which are dropped.
+    fn literal(&self, node: &Node) -> Option<String> {
+        let span = node.span?;
+        let text = self.src[span.start..span.end]
+            .trim_start_matches(|c: char| c == '(' || c.is_whitespace())
+            .trim_end_matches(|c: char| c == ')' || c.is_whitespace());
+        if text.contains("/*") || text.contains("//") {
+            return None;
+        }
+        Some(text.to_string())
+    }
+}
+
+fn struct_head(is_union: bool, tag: &Option<String>) -> String {
+    let kw = if is_union { "union" } else { "struct" };
+    match tag {
+        Some(tag) => format!("{} {}", kw, tag),
+        None => kw.to_string(),
+    }
+}
+
+fn enum_head(tag: &Option<String>) -> String {
+    match tag {
+        Some(tag) => format!("enum {}", tag),
+        None => "enum".to_string(),
+    }
+}
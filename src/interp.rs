@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ir::{Function, Label, Value, IR};
+use crate::sema::{Data, Global};
+
+// Addresses below this are never mapped, so that a null pointer
+// dereference fails instead of reading a global.
+const NULL_PAGE: usize = 0x1000;
+
+const STACK_SIZE: usize = 1 << 20;
+
+// A function ready to run: its instructions, where each label is, and how
+// many registers it uses.
+struct Code<'a> {
+    irv: &'a [IR],
+    labels: HashMap<Label, usize>,
+    nregs: usize,
+}
+
+impl<'a> Code<'a> {
+    fn new(f: &'a Function) -> Self {
+        let mut labels = HashMap::new();
+        let mut nregs = 0;
+        for (i, ir) in f.irv.iter().enumerate() {
+            if let IR::Label { label } = ir {
+                labels.insert(*label, i);
+            }
+            for v in ir.operands() {
+                match v {
+                    Value::VReg(r) => nregs = nregs.max(r.0 + 1),
+                    Value::Args(args) => {
+                        for r in args {
+                            nregs = nregs.max(r.0 + 1);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Self {
+            irv: &f.irv,
+            labels,
+            nregs,
+        }
+    }
+
+    fn label(&self, label: Label) -> usize {
+        match self.labels.get(&label) {
+            Some(pc) => *pc,
+            None => panic!("undefined label {}", label),
+        }
+    }
+}
+
+// The machine the generated code runs on: one flat memory holding the
+// globals at the bottom and a stack growing down from the top.
+struct Machine<'a> {
+    fns: HashMap<&'a str, Rc<Code<'a>>>,
+    symbols: HashMap<&'a str, usize>,
+    mem: Vec<u8>,
+    sp: usize,
+    stack_limit: usize,
+}
+
+impl<'a> Machine<'a> {
+    fn new(globals: &'a [Global], fns: &'a [Function]) -> Self {
+        let mut symbols = HashMap::new();
+        let mut addr = NULL_PAGE;
+        for var in globals {
+            addr = addr.div_ceil(var.align.max(1)) * var.align.max(1);
+            symbols.insert(var.name.as_str(), addr);
+            addr += var.size;
+        }
+
+        let stack_limit = addr.div_ceil(16) * 16;
+        let mut m = Self {
+            fns: fns
+                .iter()
+                .map(|f| (f.name.as_str(), Rc::new(Code::new(f))))
+                .collect(),
+            symbols,
+            mem: vec![0; stack_limit + STACK_SIZE],
+            sp: stack_limit + STACK_SIZE,
+            stack_limit,
+        };
+
+        for var in globals {
+            let mut addr = m.symbols[var.name.as_str()];
+            for d in var.data.iter().flatten() {
+                match d {
+                    Data::Bytes(bytes) => {
+                        m.mem[addr..addr + bytes.len()].copy_from_slice(bytes);
+                        addr += bytes.len();
+                    }
+                    Data::Label(name) => {
+                        let val = m.symbol(name) as i64;
+                        m.store(addr as i64, 8, val);
+                        addr += 8;
+                    }
+                }
+            }
+        }
+        m
+    }
+
+    fn symbol(&self, name: &str) -> usize {
+        match self.symbols.get(name) {
+            Some(addr) => *addr,
+            None => panic!("undefined symbol {}", name),
+        }
+    }
+
+    fn check(&self, addr: i64, size: usize) -> usize {
+        match usize::try_from(addr) {
+            Ok(a) if a >= NULL_PAGE && a + size <= self.mem.len() => a,
+            _ => panic!("invalid memory access at {:#x}", addr),
+        }
+    }
+
+    // Narrow loads sign-extend, as movsx does.
+    fn load(&self, addr: i64, size: usize) -> i64 {
+        let a = self.check(addr, size);
+        let mut buf = [0; 8];
+        buf[..size].copy_from_slice(&self.mem[a..a + size]);
+        let val = i64::from_le_bytes(buf);
+        let shift = 64 - size as u32 * 8;
+        (val << shift) >> shift
+    }
+
+    fn store(&mut self, addr: i64, size: usize, val: i64) {
+        let a = self.check(addr, size);
+        self.mem[a..a + size].copy_from_slice(&val.to_le_bytes()[..size]);
+    }
+
+    fn cstr(&self, addr: i64) -> Vec<u8> {
+        let mut s = vec![];
+        let mut a = addr;
+        loop {
+            let c = self.load(a, 1) as u8;
+            if c == 0 {
+                return s;
+            }
+            s.push(c);
+            a += 1;
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[i64]) -> i64 {
+        let code = match self.fns.get(name) {
+            Some(code) => code.clone(),
+            None => return self.builtin(name, args),
+        };
+        let irv = code.irv;
+        let mut regs = vec![0i64; code.nregs];
+        let saved_sp = self.sp;
+
+        let mut pc = 0;
+        let ret = loop {
+            let ir = match irv.get(pc) {
+                Some(ir) => ir,
+                None => break 0,
+            };
+            pc += 1;
+
+            use IR::*;
+            match ir {
+                Imm { lhs, imm } => regs[lhs.0] = imm.0,
+                Mov { lhs, rhs } => regs[lhs.0] = regs[rhs.0],
+                Add { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0].wrapping_add(regs[rhs.0]),
+                AddImm { dst, lhs, imm } => regs[dst.0] = regs[lhs.0].wrapping_add(imm.0),
+                Sub { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0].wrapping_sub(regs[rhs.0]),
+                Mul { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0].wrapping_mul(regs[rhs.0]),
+                Div { dst, lhs, rhs } => regs[dst.0] = div(regs[lhs.0], regs[rhs.0], name).0,
+                Mod { dst, lhs, rhs } => regs[dst.0] = div(regs[lhs.0], regs[rhs.0], name).1,
+                Shl { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0].wrapping_shl(regs[rhs.0] as u32),
+                Shr { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0].wrapping_shr(regs[rhs.0] as u32),
+                And { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0] & regs[rhs.0],
+                Or { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0] | regs[rhs.0],
+                Xor { dst, lhs, rhs } => regs[dst.0] = regs[lhs.0] ^ regs[rhs.0],
+                Eq { dst, lhs, rhs } => regs[dst.0] = (regs[lhs.0] == regs[rhs.0]) as i64,
+                Ne { dst, lhs, rhs } => regs[dst.0] = (regs[lhs.0] != regs[rhs.0]) as i64,
+                Lt { dst, lhs, rhs } => regs[dst.0] = (regs[lhs.0] < regs[rhs.0]) as i64,
+                Le { dst, lhs, rhs } => regs[dst.0] = (regs[lhs.0] <= regs[rhs.0]) as i64,
+                Label { .. } | Kill { .. } | Nop => (),
+                Jmp { label } => pc = code.label(*label),
+                Unless { lhs, label } => {
+                    if regs[lhs.0] == 0 {
+                        pc = code.label(*label);
+                    }
+                }
+                JumpTable {
+                    lhs,
+                    default,
+                    targets,
+                } => {
+                    let label = targets.get(regs[lhs.0] as usize).unwrap_or(default);
+                    pc = code.label(*label);
+                }
+                Return { lhs } => break regs[lhs.0],
+                Alloca { lhs, size } => {
+                    let size = size.0 as usize;
+                    if self.sp < self.stack_limit + size {
+                        panic!("stack overflow in {}", name);
+                    }
+                    self.sp -= size;
+                    regs[lhs.0] = self.sp as i64;
+                }
+                Load8 { lhs, rhs } => regs[lhs.0] = self.load(regs[rhs.0], 1),
+                Load16 { lhs, rhs } => regs[lhs.0] = self.load(regs[rhs.0], 2),
+                Load32 { lhs, rhs } => regs[lhs.0] = self.load(regs[rhs.0], 4),
+                Load64 { lhs, rhs } => regs[lhs.0] = self.load(regs[rhs.0], 8),
+                Store8 { lhs, rhs } => self.store(regs[lhs.0], 1, regs[rhs.0]),
+                Store16 { lhs, rhs } => self.store(regs[lhs.0], 2, regs[rhs.0]),
+                Store32 { lhs, rhs } => self.store(regs[lhs.0], 4, regs[rhs.0]),
+                Store64 { lhs, rhs } => self.store(regs[lhs.0], 8, regs[rhs.0]),
+                StoreArg8 { lhs, slot } => self.store(regs[lhs.0], 1, arg(args, slot.0)),
+                StoreArg16 { lhs, slot } => self.store(regs[lhs.0], 2, arg(args, slot.0)),
+                StoreArg32 { lhs, slot } => self.store(regs[lhs.0], 4, arg(args, slot.0)),
+                StoreArg64 { lhs, slot } => self.store(regs[lhs.0], 8, arg(args, slot.0)),
+                Sext8 { lhs } => regs[lhs.0] = regs[lhs.0] as i8 as i64,
+                Sext16 { lhs } => regs[lhs.0] = regs[lhs.0] as i16 as i64,
+                Sext32 { lhs } => regs[lhs.0] = regs[lhs.0] as i32 as i64,
+                LabelAddr { lhs, name } => regs[lhs.0] = self.symbol(name) as i64,
+                Call {
+                    lhs,
+                    name,
+                    args: arg_regs,
+                } => {
+                    let args: Vec<i64> = arg_regs.iter().map(|r| regs[r.0]).collect();
+                    regs[lhs.0] = self.call(name, &args);
+                }
+            }
+        };
+
+        self.sp = saved_sp;
+        ret
+    }
+
+    // Library functions the test programs call
+    fn builtin(&mut self, name: &str, args: &[i64]) -> i64 {
+        match name {
+            "printf" => {
+                let s = self.printf(args);
+                print!("{}", String::from_utf8_lossy(&s));
+                s.len() as i64
+            }
+            "puts" => {
+                let s = self.cstr(args[0]);
+                println!("{}", String::from_utf8_lossy(&s));
+                s.len() as i64 + 1
+            }
+            _ => panic!("undefined function {}", name),
+        }
+    }
+
+    // Supports %d, %ld, %c, %s, %x and %%.
+    fn printf(&self, args: &[i64]) -> Vec<u8> {
+        let fmt = self.cstr(args[0]);
+        let mut args = args[1..].iter();
+        let mut next = || match args.next() {
+            Some(arg) => *arg,
+            None => panic!("printf: too few arguments"),
+        };
+
+        let mut out = vec![];
+        let mut i = 0;
+        while i < fmt.len() {
+            if fmt[i] != b'%' {
+                out.push(fmt[i]);
+                i += 1;
+                continue;
+            }
+            i += 1;
+            let long = fmt.get(i) == Some(&b'l');
+            if long {
+                i += 1;
+            }
+            let val = |v: i64| if long { v } else { v as i32 as i64 };
+            match fmt.get(i) {
+                Some(b'd') | Some(b'i') => out.extend(val(next()).to_string().bytes()),
+                Some(b'x') if long => out.extend(format!("{:x}", next() as u64).bytes()),
+                Some(b'x') => out.extend(format!("{:x}", next() as u32).bytes()),
+                Some(b'c') => out.push(next() as u8),
+                Some(b's') => out.extend(self.cstr(next())),
+                Some(b'%') => out.push(b'%'),
+                _ => panic!(
+                    "printf: unsupported format {}",
+                    String::from_utf8_lossy(&fmt)
+                ),
+            }
+            i += 1;
+        }
+        out
+    }
+}
+
+// Quotient and remainder, as idiv computes them
+fn div(a: i64, b: i64, name: &str) -> (i64, i64) {
+    if b == 0 {
+        panic!("division by zero in {}", name);
+    }
+    (a.wrapping_div(b), a.wrapping_rem(b))
+}
+
+// Arguments missing in a call read as 0, like a register nobody set.
+fn arg(args: &[i64], slot: usize) -> i64 {
+    args.get(slot).copied().unwrap_or(0)
+}
+
+// Runs main and returns its value, writing whatever the program prints to
+// stdout. Registers are whatever the instructions name, so this runs the
+// IR both before and after alloc_regs.
+pub fn run(globals: &[Global], fns: &[Function]) -> i64 {
+    Machine::new(globals, fns).call("main", &[])
+}
@@ -2,243 +2,995 @@ use std::{collections::HashMap, fmt, sync::Mutex};
 
 use lazy_static::lazy_static;
 
-use crate::parse::{Node, NodeType};
+use crate::parse::{roundup, Node, NodeType, Type};
+use crate::sema::{self, Data, Global};
 use crate::token::TokenType;
 
 lazy_static! {
-    static ref VARS: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
     static ref REGNO: Mutex<usize> = Mutex::new(1);
-    static ref BASE_REG: Mutex<usize> = Mutex::new(0);
-    static ref BPOFF: Mutex<usize> = Mutex::new(0);
+    static ref BASE_REG: Mutex<VReg> = Mutex::new(VReg(0));
     static ref LABEL: Mutex<usize> = Mutex::new(0);
-    static ref IRINFO: [IRInfo; 16] = [
-        IRInfo::new(IROp::Add, "+", IRType::RegReg),
-        IRInfo::new(IROp::Sub, "-", IRType::RegReg),
-        IRInfo::new(IROp::Mul, "*", IRType::RegReg),
-        IRInfo::new(IROp::Div, "/", IRType::RegReg),
-        IRInfo::new(IROp::Imm, "MOV", IRType::RegImm),
-        IRInfo::new(IROp::AddImm, "ADD", IRType::RegImm),
-        IRInfo::new(IROp::Mov, "MOV", IRType::RegReg),
-        IRInfo::new(IROp::Label, "", IRType::Label),
-        IRInfo::new(IROp::Jmp, "", IRType::Label),
-        IRInfo::new(IROp::Unless, "UNLESS", IRType::RegLabel),
-        IRInfo::new(IROp::Return, "RET", IRType::Reg),
-        IRInfo::new(IROp::Alloca, "ALLOCA", IRType::RegImm),
-        IRInfo::new(IROp::Load, "LOAD", IRType::RegReg),
-        IRInfo::new(IROp::Store, "STORE", IRType::RegReg),
-        IRInfo::new(IROp::Kill, "KILL", IRType::Reg),
-        IRInfo::new(IROp::Nop, "NOP", IRType::NoArg),
-    ];
-}
-
-#[derive(Clone)]
-pub enum IRType {
-    NoArg,
-    Reg,
-    Label,
-    RegReg,
-    RegImm,
-    RegLabel,
+    static ref SWITCHES: Mutex<Vec<SwitchLabels>> = Mutex::new(vec![]);
+    static ref USER_LABELS: Mutex<HashMap<String, Label>> = Mutex::new(HashMap::new());
+    static ref ARG_SLOTS: Mutex<ArgSlots> = Mutex::new(ArgSlots::default());
 }
 
-#[derive(Clone)]
-pub struct IRInfo {
-    op: IROp,
-    name: &'static str,
-    pub ty: IRType,
+// A register: virtual out of gen_ir, and an index into REGS after
+// alloc_regs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VReg(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Imm(pub i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(pub usize);
+
+// The position of a function argument, 0 for the first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot(pub usize);
+
+// A global or function name
+pub type Symbol = String;
+// Argument registers of a call
+pub type Args = Vec<VReg>;
+// Labels of a jump table
+pub type Targets = Vec<Label>;
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "r{}", self.0)
+    }
 }
 
-impl IRInfo {
-    pub fn new(op: IROp, name: &'static str, ty: IRType) -> Self {
-        Self { op, name, ty }
+impl fmt::Display for Imm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum IROp {
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ".L{}", self.0)
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    VReg,
     Imm,
-    Mov,
-    Add,
-    AddImm,
-    Sub,
-    Mul,
-    Div,
     Label,
-    Jmp,
-    Unless,
-    Return,
-    Alloca,
-    Load,
-    Store,
-    Kill,
-    Nop,
+    Slot,
+    Symbol,
+    Args,
+    Targets,
+}
+
+// An operand of any kind, for code that handles instructions generically.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    VReg(VReg),
+    Imm(Imm),
+    Label(Label),
+    Slot(Slot),
+    Symbol(Symbol),
+    Args(Args),
+    Targets(Targets),
+}
+
+pub trait Operand: Sized {
+    const KIND: OperandKind;
+    fn value(&self) -> Value;
+    fn from_value(v: Value) -> Option<Self>;
+    fn map_regs(&mut self, _f: &mut impl FnMut(VReg) -> VReg) {}
+}
+
+impl Operand for VReg {
+    const KIND: OperandKind = OperandKind::VReg;
+    fn value(&self) -> Value {
+        Value::VReg(*self)
+    }
+    fn from_value(v: Value) -> Option<Self> {
+        match v {
+            Value::VReg(r) => Some(r),
+            _ => None,
+        }
+    }
+    fn map_regs(&mut self, f: &mut impl FnMut(VReg) -> VReg) {
+        *self = f(*self);
+    }
+}
+
+impl Operand for Imm {
+    const KIND: OperandKind = OperandKind::Imm;
+    fn value(&self) -> Value {
+        Value::Imm(*self)
+    }
+    fn from_value(v: Value) -> Option<Self> {
+        match v {
+            Value::Imm(imm) => Some(imm),
+            _ => None,
+        }
+    }
+}
+
+impl Operand for Label {
+    const KIND: OperandKind = OperandKind::Label;
+    fn value(&self) -> Value {
+        Value::Label(*self)
+    }
+    fn from_value(v: Value) -> Option<Self> {
+        match v {
+            Value::Label(l) => Some(l),
+            _ => None,
+        }
+    }
+}
+
+impl Operand for Slot {
+    const KIND: OperandKind = OperandKind::Slot;
+    fn value(&self) -> Value {
+        Value::Slot(*self)
+    }
+    fn from_value(v: Value) -> Option<Self> {
+        match v {
+            Value::Slot(slot) => Some(slot),
+            _ => None,
+        }
+    }
+}
+
+impl Operand for Symbol {
+    const KIND: OperandKind = OperandKind::Symbol;
+    fn value(&self) -> Value {
+        Value::Symbol(self.clone())
+    }
+    fn from_value(v: Value) -> Option<Self> {
+        match v {
+            Value::Symbol(name) => Some(name),
+            _ => None,
+        }
+    }
 }
 
-impl From<NodeType> for IROp {
-    fn from(t: NodeType) -> Self {
-        match t {
-            NodeType::BinOp(op, _, _) => Self::from(op),
-            e => panic!("cannot convert: {:?}", e),
+impl Operand for Args {
+    const KIND: OperandKind = OperandKind::Args;
+    fn value(&self) -> Value {
+        Value::Args(self.clone())
+    }
+    fn from_value(v: Value) -> Option<Self> {
+        match v {
+            Value::Args(args) => Some(args),
+            _ => None,
+        }
+    }
+    fn map_regs(&mut self, f: &mut impl FnMut(VReg) -> VReg) {
+        for r in self.iter_mut() {
+            *r = f(*r);
         }
     }
 }
 
-impl From<TokenType> for IROp {
-    fn from(t: TokenType) -> Self {
-        match t {
-            TokenType::Plus => IROp::Add,
-            TokenType::Minus => IROp::Sub,
-            TokenType::Mul => IROp::Mul,
-            TokenType::Div => IROp::Div,
-            e => panic!("cannot convert: {:?}", e),
+impl Operand for Targets {
+    const KIND: OperandKind = OperandKind::Targets;
+    fn value(&self) -> Value {
+        Value::Targets(self.clone())
+    }
+    fn from_value(v: Value) -> Option<Self> {
+        match v {
+            Value::Targets(labels) => Some(labels),
+            _ => None,
         }
     }
 }
 
-#[derive(Clone)]
-pub struct IR {
-    pub op: IROp,
-    pub lhs: Option<usize>,
-    pub rhs: Option<usize>,
+pub struct IRInfo {
+    pub name: &'static str,
+    pub operands: Vec<OperandKind>,
+}
+
+// Declares the IR enum together with IRINFO, so an instruction's mnemonic
+// and the kinds of its operands are written once, next to the variant,
+// and everything that walks operands goes through their types.
+macro_rules! instructions {
+    ($($op:ident $({ $($field:ident: $ty:ident),* })? => $name:literal,)*) => {
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum IR {
+            $($op $({ $($field: $ty),* })?,)*
+        }
+
+        lazy_static! {
+            static ref IRINFO: Vec<IRInfo> = vec![
+                $(IRInfo {
+                    name: $name,
+                    operands: vec![$($(<$ty as Operand>::KIND),*)?],
+                },)*
+            ];
+        }
+
+        impl IR {
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(IR::$op { .. } => $name,)*
+                }
+            }
+
+            // The operands in the order they are written
+            pub fn operands(&self) -> Vec<Value> {
+                match self {
+                    $(IR::$op $({ $($field),* })? => vec![$($($field.value()),*)?],)*
+                }
+            }
+
+            // The instruction with the given mnemonic, if the operands are
+            // of the kinds IRINFO lists for it.
+            pub fn build(name: &str, values: Vec<Value>) -> Option<IR> {
+                let mut values = values.into_iter();
+                let ir = match name {
+                    $($name => IR::$op $({
+                        $($field: <$ty as Operand>::from_value(values.next()?)?),*
+                    })?,)*
+                    _ => return None,
+                };
+                match values.next() {
+                    Some(_) => None,
+                    None => Some(ir),
+                }
+            }
+
+            // Replaces every register operand with f of it, in the order
+            // they are written.
+            pub fn map_regs(&mut self, f: &mut impl FnMut(VReg) -> VReg) {
+                match self {
+                    $(IR::$op $({ $($field),* })? => { $($($field.map_regs(f);)*)? })*
+                }
+            }
+        }
+    };
+}
+
+// Arithmetic is three-address: `ADD r3, r1, r2` sets r3 to r1 + r2.
+// Loads set lhs from the address in rhs, and stores write rhs to the
+// address in lhs.
+instructions! {
+    Add { dst: VReg, lhs: VReg, rhs: VReg } => "ADD",
+    Sub { dst: VReg, lhs: VReg, rhs: VReg } => "SUB",
+    Mul { dst: VReg, lhs: VReg, rhs: VReg } => "MUL",
+    Div { dst: VReg, lhs: VReg, rhs: VReg } => "DIV",
+    Mod { dst: VReg, lhs: VReg, rhs: VReg } => "MOD",
+    Shl { dst: VReg, lhs: VReg, rhs: VReg } => "SHL",
+    Shr { dst: VReg, lhs: VReg, rhs: VReg } => "SHR",
+    And { dst: VReg, lhs: VReg, rhs: VReg } => "AND",
+    Or { dst: VReg, lhs: VReg, rhs: VReg } => "OR",
+    Xor { dst: VReg, lhs: VReg, rhs: VReg } => "XOR",
+    Eq { dst: VReg, lhs: VReg, rhs: VReg } => "EQ",
+    Ne { dst: VReg, lhs: VReg, rhs: VReg } => "NE",
+    Lt { dst: VReg, lhs: VReg, rhs: VReg } => "LT",
+    Le { dst: VReg, lhs: VReg, rhs: VReg } => "LE",
+    Imm { lhs: VReg, imm: Imm } => "IMM",
+    AddImm { dst: VReg, lhs: VReg, imm: Imm } => "ADD_IMM",
+    Mov { lhs: VReg, rhs: VReg } => "MOV",
+    Label { label: Label } => "",
+    Jmp { label: Label } => "JMP",
+    Unless { lhs: VReg, label: Label } => "UNLESS",
+    JumpTable { lhs: VReg, default: Label, targets: Targets } => "JTABLE",
+    Return { lhs: VReg } => "RET",
+    Alloca { lhs: VReg, size: Imm } => "ALLOCA",
+    Load8 { lhs: VReg, rhs: VReg } => "LOAD8",
+    Load16 { lhs: VReg, rhs: VReg } => "LOAD16",
+    Load32 { lhs: VReg, rhs: VReg } => "LOAD32",
+    Load64 { lhs: VReg, rhs: VReg } => "LOAD64",
+    Store8 { lhs: VReg, rhs: VReg } => "STORE8",
+    Store16 { lhs: VReg, rhs: VReg } => "STORE16",
+    Store32 { lhs: VReg, rhs: VReg } => "STORE32",
+    Store64 { lhs: VReg, rhs: VReg } => "STORE64",
+    StoreArg8 { lhs: VReg, slot: Slot } => "STORE_ARG8",
+    StoreArg16 { lhs: VReg, slot: Slot } => "STORE_ARG16",
+    StoreArg32 { lhs: VReg, slot: Slot } => "STORE_ARG32",
+    StoreArg64 { lhs: VReg, slot: Slot } => "STORE_ARG64",
+    Sext8 { lhs: VReg } => "SEXT8",
+    Sext16 { lhs: VReg } => "SEXT16",
+    Sext32 { lhs: VReg } => "SEXT32",
+    LabelAddr { lhs: VReg, name: Symbol } => "LEA",
+    Call { lhs: VReg, name: Symbol, args: Args } => "CALL",
+    Kill { lhs: VReg } => "KILL",
+    Nop => "NOP",
+}
+
+// The instruction computing `lhs op rhs` into dst
+fn binop(op: TokenType, dst: VReg, lhs: VReg, rhs: VReg) -> IR {
+    match op {
+        TokenType::Plus | TokenType::AddEq => IR::Add { dst, lhs, rhs },
+        TokenType::Minus | TokenType::SubEq => IR::Sub { dst, lhs, rhs },
+        TokenType::Mul | TokenType::MulEq => IR::Mul { dst, lhs, rhs },
+        TokenType::Div | TokenType::DivEq => IR::Div { dst, lhs, rhs },
+        TokenType::Mod | TokenType::ModEq => IR::Mod { dst, lhs, rhs },
+        TokenType::Shl | TokenType::ShlEq => IR::Shl { dst, lhs, rhs },
+        TokenType::Shr | TokenType::ShrEq => IR::Shr { dst, lhs, rhs },
+        TokenType::And | TokenType::AndEq => IR::And { dst, lhs, rhs },
+        TokenType::Or | TokenType::OrEq => IR::Or { dst, lhs, rhs },
+        TokenType::Xor | TokenType::XorEq => IR::Xor { dst, lhs, rhs },
+        TokenType::EqEq => IR::Eq { dst, lhs, rhs },
+        TokenType::Ne => IR::Ne { dst, lhs, rhs },
+        TokenType::Lt => IR::Lt { dst, lhs, rhs },
+        TokenType::Le => IR::Le { dst, lhs, rhs },
+        e => panic!("cannot convert: {:?}", e),
+    }
 }
 
 impl IR {
-    fn new(op: IROp, lhs: Option<usize>, rhs: Option<usize>) -> Self {
-        Self { op, lhs, rhs }
+    // The destination, left and right operands of arithmetic. The right
+    // operand of ADD_IMM is an immediate.
+    pub fn arith_operands_mut(&mut self) -> Option<(&mut VReg, &mut VReg, Option<&mut VReg>)> {
+        use IR::*;
+
+        match self {
+            Add { dst, lhs, rhs }
+            | Sub { dst, lhs, rhs }
+            | Mul { dst, lhs, rhs }
+            | Div { dst, lhs, rhs }
+            | Mod { dst, lhs, rhs }
+            | Shl { dst, lhs, rhs }
+            | Shr { dst, lhs, rhs }
+            | And { dst, lhs, rhs }
+            | Or { dst, lhs, rhs }
+            | Xor { dst, lhs, rhs }
+            | Eq { dst, lhs, rhs }
+            | Ne { dst, lhs, rhs }
+            | Lt { dst, lhs, rhs }
+            | Le { dst, lhs, rhs } => Some((dst, lhs, Some(rhs))),
+            AddImm { dst, lhs, .. } => Some((dst, lhs, None)),
+            _ => None,
+        }
     }
 }
 
+// The textual form of an instruction, which parse_ir reads back: a
+// mnemonic followed by its operands. Labels are written `.L3:`, and a
+// call's arguments follow the function name, as in `CALL r1, f(r2, r3)`.
 impl fmt::Display for IR {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use IRType::*;
+        if let IR::Label { label } = self {
+            return write!(f, "{}:", label);
+        }
+
+        let mut ops: Vec<String> = vec![];
+        for v in self.operands() {
+            match v {
+                Value::VReg(r) => ops.push(r.to_string()),
+                Value::Imm(imm) => ops.push(imm.to_string()),
+                Value::Label(l) => ops.push(l.to_string()),
+                Value::Slot(slot) => ops.push(slot.to_string()),
+                Value::Symbol(name) => ops.push(name),
+                Value::Args(args) => {
+                    let args: Vec<String> = args.iter().map(|r| r.to_string()).collect();
+                    *ops.last_mut().unwrap() += &format!("({})", args.join(", "));
+                }
+                Value::Targets(labels) => {
+                    let labels: Vec<String> = labels.iter().map(|l| l.to_string()).collect();
+                    ops.push(format!("[{}]", labels.join(", ")));
+                }
+            }
+        }
 
-        let info = get_irinfo(self);
-        let lhs = self.lhs.unwrap();
-        match info.ty {
-            Label => writeln!(f, ".L{}=>", lhs),
-            Reg => writeln!(f, "{} r{}", info.name, lhs),
-            RegReg => writeln!(f, "{} r{}, r{}", info.name, lhs, self.rhs.unwrap()),
-            RegImm => writeln!(f, "{} r{}, {}", info.name, lhs, self.rhs.unwrap()),
-            RegLabel => writeln!(f, "{} r{}, L{}", info.name, lhs, self.rhs.unwrap()),
-            NoArg => writeln!(f, "{}", info.name),
+        if ops.is_empty() {
+            write!(f, "{}", self.name())
+        } else {
+            write!(f, "{} {}", self.name(), ops.join(", "))
         }
     }
 }
 
-pub fn dump_ir(irv: &Vec<IR>) {
-    for ir in irv {
-        println!("{}", ir);
-    }
+// Labels of the innermost switch statement being generated.
+struct SwitchLabels {
+    cases: HashMap<i64, Label>,
+    default: Option<Label>,
+    brk: Label,
+}
+
+// Stack slots above the locals where call arguments wait while a later
+// argument makes another call. Offsets are relative to the first slot.
+#[derive(Default)]
+struct ArgSlots {
+    base: usize,
+    used: usize,
+    max: usize,
 }
 
-pub fn get_irinfo(ir: &IR) -> IRInfo {
-    for info in IRINFO.iter() {
-        if info.op == ir.op {
-            return info.clone();
+pub struct Function {
+    pub name: String,
+    pub irv: Vec<IR>,
+}
+
+// The globals and functions in the syntax parse_ir reads.
+pub fn ir_text(globals: &[Global], fns: &[Function]) -> String {
+    let mut out = String::new();
+    for var in globals {
+        let section = match var.data {
+            None => ".bss",
+            Some(_) if var.readonly => ".rodata",
+            Some(_) => ".data",
+        };
+        out += &format!("{} {}, {}, {}\n", section, var.name, var.size, var.align);
+        for d in var.data.iter().flatten() {
+            match d {
+                Data::Bytes(bytes) => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+                    out += &format!("  .byte {}\n", bytes.join(", "));
+                }
+                Data::Label(name) => out += &format!("  .quad {}\n", name),
+            }
+        }
+    }
+
+    for (i, f) in fns.iter().enumerate() {
+        if i > 0 || !globals.is_empty() {
+            out.push('\n');
+        }
+        out += &format!("{}():\n", f.name);
+        for ir in f.irv.iter() {
+            match ir {
+                IR::Label { .. } => out += &format!("{}\n", ir),
+                _ => out += &format!("  {}\n", ir),
+            }
         }
     }
-    panic!("invalid instruction")
+    out
 }
 
-fn gen_lval(code: &mut Vec<IR>, node: Node) -> Option<usize> {
-    match node.ty {
-        NodeType::Ident(name) => {
-            if VARS.lock().unwrap().get(&name).is_none() {
-                VARS.lock()
-                    .unwrap()
-                    .insert(name.to_string(), *BPOFF.lock().unwrap());
-                *BPOFF.lock().unwrap() += 8;
-            }
-            let r = Some(*REGNO.lock().unwrap());
-            *REGNO.lock().unwrap() += 1;
-            let off = *VARS.lock().unwrap().get(&name).unwrap();
-            code.push(IR::new(IROp::Mov, r, Some(*BASE_REG.lock().unwrap())));
-            code.push(IR::new(IROp::AddImm, r, Some(off)));
+pub fn dump_ir(globals: &[Global], fns: &[Function]) {
+    print!("{}", ir_text(globals, fns));
+}
+
+pub fn find_irinfo(name: &str) -> Option<&'static IRInfo> {
+    IRINFO.iter().find(|info| info.name == name)
+}
 
+fn load_insn(size: usize, lhs: VReg, rhs: VReg) -> IR {
+    match size {
+        1 => IR::Load8 { lhs, rhs },
+        2 => IR::Load16 { lhs, rhs },
+        4 => IR::Load32 { lhs, rhs },
+        8 => IR::Load64 { lhs, rhs },
+        _ => unreachable!(),
+    }
+}
+
+fn store_insn(size: usize, lhs: VReg, rhs: VReg) -> IR {
+    match size {
+        1 => IR::Store8 { lhs, rhs },
+        2 => IR::Store16 { lhs, rhs },
+        4 => IR::Store32 { lhs, rhs },
+        8 => IR::Store64 { lhs, rhs },
+        _ => unreachable!(),
+    }
+}
+
+fn store_arg_insn(ty: &Type, lhs: VReg, slot: Slot) -> IR {
+    match ty.size() {
+        1 => IR::StoreArg8 { lhs, slot },
+        2 => IR::StoreArg16 { lhs, slot },
+        4 => IR::StoreArg32 { lhs, slot },
+        8 => IR::StoreArg64 { lhs, slot },
+        _ => unreachable!(),
+    }
+}
+
+fn new_reg() -> VReg {
+    let r = VReg(*REGNO.lock().unwrap());
+    *REGNO.lock().unwrap() += 1;
+    r
+}
+
+fn kill(code: &mut Vec<IR>, lhs: VReg) {
+    code.push(IR::Kill { lhs });
+}
+
+fn gen_lval(code: &mut Vec<IR>, node: Node) -> VReg {
+    match node.ty {
+        NodeType::Lvar(off) => {
+            let r = new_reg();
+            code.push(IR::AddImm {
+                dst: r,
+                lhs: *BASE_REG.lock().unwrap(),
+                imm: Imm(off as i64),
+            });
+            r
+        }
+        NodeType::Gvar(name) => {
+            let r = new_reg();
+            code.push(IR::LabelAddr { lhs: r, name });
+            r
+        }
+        NodeType::Deref(expr) => gen_expr(code, *expr),
+        NodeType::Dot(expr, name) => {
+            let offset = expr.ctype.member(&name).unwrap().offset;
+            let base = gen_lval(code, *expr);
+            let r = new_reg();
+            code.push(IR::AddImm {
+                dst: r,
+                lhs: base,
+                imm: Imm(offset as i64),
+            });
+            kill(code, base);
             r
         }
         _ => panic!("not a local value"),
     }
 }
 
-fn gen_expr(code: &mut Vec<IR>, node: Node) -> Option<usize> {
+// Copies `size` bytes from the address in `src` to the address in `dst`.
+fn gen_copy(code: &mut Vec<IR>, dst: VReg, src: VReg, size: usize) {
+    let mut off = 0;
+    for chunk in [8, 4, 2, 1] {
+        while size - off >= chunk {
+            let imm = Imm(off as i64);
+            let s = new_reg();
+            code.push(IR::AddImm {
+                dst: s,
+                lhs: src,
+                imm,
+            });
+            let tmp = new_reg();
+            code.push(load_insn(chunk, tmp, s));
+            kill(code, s);
+
+            let d = new_reg();
+            code.push(IR::AddImm {
+                dst: d,
+                lhs: dst,
+                imm,
+            });
+            code.push(store_insn(chunk, d, tmp));
+            kill(code, d);
+            kill(code, tmp);
+            off += chunk;
+        }
+    }
+}
+
+// Sign-extends the low bits of `lhs` to a full register, truncating the
+// value to `ty`.
+fn gen_sext(code: &mut Vec<IR>, lhs: VReg, ty: &Type) {
+    match ty.size() {
+        1 => code.push(IR::Sext8 { lhs }),
+        2 => code.push(IR::Sext16 { lhs }),
+        4 => code.push(IR::Sext32 { lhs }),
+        _ => (),
+    }
+}
+
+fn gen_expr(code: &mut Vec<IR>, node: Node) -> VReg {
     match node.ty {
-        NodeType::Num(val) => {
-            let r = Some(*REGNO.lock().unwrap());
-            *REGNO.lock().unwrap() += 1;
-            code.push(IR::new(IROp::Imm, r, Some(val as usize)));
+        NodeType::Num(val) => gen_imm(code, val),
+        NodeType::Lvar(_) | NodeType::Gvar(_) | NodeType::Deref(_) | NodeType::Dot(..) => {
+            // The value of a struct is its address.
+            if node.ctype.is_struct() {
+                return gen_lval(code, node);
+            }
+            let size = node.ctype.size();
+            let r = gen_lval(code, node);
+            code.push(load_insn(size, r, r));
             r
         }
-        NodeType::Ident(_) => {
-            let r = gen_lval(code, node);
-            code.push(IR::new(IROp::Load, r, r));
+        NodeType::Call(name, args) => {
+            // Slots are taken before the arguments are generated, so that
+            // calls within them use slots of their own.
+            let start = ARG_SLOTS.lock().unwrap().used;
+            ARG_SLOTS.lock().unwrap().used += args.len() * 8;
+
+            let args: Vec<(Vec<IR>, VReg)> = args
+                .into_iter()
+                .map(|arg| {
+                    let mut c = vec![];
+                    let r = gen_expr(&mut c, arg);
+                    (c, r)
+                })
+                .collect();
+
+            // Arguments before the last one that makes a call are stored
+            // as soon as they are evaluated, so that nested calls don't
+            // use up the registers.
+            let nspill = args
+                .iter()
+                .rposition(|(c, _)| c.iter().any(|ir| matches!(ir, IR::Call { .. })))
+                .unwrap_or(0);
+            let mut slots = ARG_SLOTS.lock().unwrap();
+            slots.max = slots.max.max(start + nspill * 8);
+            let base = slots.base + start;
+            drop(slots);
+
+            let mut regs = vec![];
+            for (i, (c, r)) in args.into_iter().enumerate() {
+                code.extend(c);
+                if i < nspill {
+                    let addr = gen_arg_slot(code, base + i * 8);
+                    code.push(IR::Store64 { lhs: addr, rhs: r });
+                    kill(code, addr);
+                    kill(code, r);
+                } else {
+                    regs.push(r);
+                }
+            }
+            let mut args = vec![];
+            for i in 0..nspill {
+                let addr = gen_arg_slot(code, base + i * 8);
+                let r = new_reg();
+                code.push(IR::Load64 { lhs: r, rhs: addr });
+                kill(code, addr);
+                args.push(r);
+            }
+            args.extend(regs);
+            ARG_SLOTS.lock().unwrap().used = start;
+
+            let r = new_reg();
+            code.push(IR::Call { lhs: r, name, args });
+            r
+        }
+        NodeType::Addr(expr) => gen_lval(code, *expr),
+        NodeType::Cast(expr) => {
+            let r = gen_expr(code, *expr);
+            gen_sext(code, r, &node.ctype);
+            r
+        }
+        NodeType::Ternary(cond, then, els) => {
+            // Both arms leave their value in the same register.
+            let r = new_reg();
+            let x = new_label();
+            let y = new_label();
+
+            let c = gen_expr(code, *cond);
+            code.push(IR::Unless { lhs: c, label: x });
+            kill(code, c);
+
+            let r1 = gen_expr(code, *then);
+            code.push(IR::Mov { lhs: r, rhs: r1 });
+            kill(code, r1);
+            code.push(IR::Jmp { label: y });
+
+            code.push(IR::Label { label: x });
+            let r2 = gen_expr(code, *els);
+            code.push(IR::Mov { lhs: r, rhs: r2 });
+            kill(code, r2);
+            code.push(IR::Label { label: y });
             r
         }
         NodeType::BinOp(op, lhs, rhs) => match op {
-            TokenType::Equal => {
+            TokenType::Equal if lhs.ctype.is_struct() => {
+                let size = lhs.ctype.size();
                 let rhs = gen_expr(code, *rhs);
                 let lhs = gen_lval(code, *lhs);
-                code.push(IR::new(IROp::Store, lhs, rhs));
-                code.push(IR::new(IROp::Kill, rhs, None));
+                gen_copy(code, lhs, rhs, size);
+                kill(code, rhs);
                 lhs
             }
+            TokenType::Equal => {
+                let size = lhs.ctype.size();
+                let rhs = gen_expr(code, *rhs);
+                let lhs = gen_lval(code, *lhs);
+                code.push(store_insn(size, lhs, rhs));
+                kill(code, lhs);
+                rhs
+            }
+            TokenType::Comma => {
+                let lhs = gen_expr(code, *lhs);
+                kill(code, lhs);
+                gen_expr(code, *rhs)
+            }
+            TokenType::LogAnd => {
+                // A zero operand jumps out with the zero as the result.
+                let x = new_label();
+                let r = gen_expr(code, *lhs);
+                code.push(IR::Unless { lhs: r, label: x });
+                let r2 = gen_expr(code, *rhs);
+                code.push(IR::Mov { lhs: r, rhs: r2 });
+                kill(code, r2);
+                code.push(IR::Unless { lhs: r, label: x });
+                code.push(IR::Imm {
+                    lhs: r,
+                    imm: Imm(1),
+                });
+                code.push(IR::Label { label: x });
+                r
+            }
+            TokenType::LogOr => {
+                let x = new_label();
+                let y = new_label();
+                let r = gen_expr(code, *lhs);
+                code.push(IR::Unless { lhs: r, label: x });
+                code.push(IR::Imm {
+                    lhs: r,
+                    imm: Imm(1),
+                });
+                code.push(IR::Jmp { label: y });
+                code.push(IR::Label { label: x });
+                let r2 = gen_expr(code, *rhs);
+                code.push(IR::Mov { lhs: r, rhs: r2 });
+                kill(code, r2);
+                code.push(IR::Unless { lhs: r, label: y });
+                code.push(IR::Imm {
+                    lhs: r,
+                    imm: Imm(1),
+                });
+                code.push(IR::Label { label: y });
+                r
+            }
+            TokenType::Gt | TokenType::Ge => {
+                // a > b is b < a, with the operands still evaluated in order.
+                let op = if op == TokenType::Gt {
+                    TokenType::Lt
+                } else {
+                    TokenType::Le
+                };
+                let lhs = gen_expr(code, *lhs);
+                let rhs = gen_expr(code, *rhs);
+                let r = new_reg();
+                code.push(binop(op, r, rhs, lhs));
+                kill(code, lhs);
+                kill(code, rhs);
+                r
+            }
+            _ if op.is_compound_assign() => {
+                // The address is computed once and used for both the load
+                // and the store.
+                let ty = lhs.ctype.clone();
+                let addr = gen_lval(code, *lhs);
+                let val = new_reg();
+                code.push(load_insn(ty.size(), val, addr));
+
+                let rhs = gen_expr(code, *rhs);
+                let r = new_reg();
+                code.push(binop(op, r, val, rhs));
+                kill(code, val);
+                kill(code, rhs);
+                gen_sext(code, r, &ty);
+                code.push(store_insn(ty.size(), addr, r));
+                kill(code, addr);
+                r
+            }
             _ => {
                 let lhs = gen_expr(code, *lhs);
                 let rhs = gen_expr(code, *rhs);
-                code.push(IR::new(IROp::from(op), lhs, rhs));
-                code.push(IR::new(IROp::Kill, rhs, None));
-                lhs
+                let r = new_reg();
+                code.push(binop(op, r, lhs, rhs));
+                kill(code, lhs);
+                kill(code, rhs);
+                r
             }
         },
         _ => unreachable!(),
     }
 }
 
+fn new_label() -> Label {
+    let l = *LABEL.lock().unwrap();
+    *LABEL.lock().unwrap() += 1;
+    Label(l)
+}
+
+// Source labels share the numbering of generated ones, so a goto is
+// just a Jmp.
+fn user_label(name: String) -> Label {
+    if let Some(l) = USER_LABELS.lock().unwrap().get(&name) {
+        return *l;
+    }
+    let l = new_label();
+    USER_LABELS.lock().unwrap().insert(name, l);
+    l
+}
+
+fn gen_imm(code: &mut Vec<IR>, val: i64) -> VReg {
+    let r = new_reg();
+    code.push(IR::Imm {
+        lhs: r,
+        imm: Imm(val),
+    });
+    r
+}
+
+// Case values are dense enough for a jump table if they cover at least
+// a third of their range.
+fn is_dense(cases: &[i64]) -> bool {
+    if cases.len() < 4 {
+        return false;
+    }
+    let min = *cases.iter().min().unwrap();
+    let max = *cases.iter().max().unwrap();
+    (max as i128 - min as i128) < 3 * cases.len() as i128
+}
+
+// Jumps to the case matching the value in `r`, or to `default`.
+fn gen_dispatch(code: &mut Vec<IR>, r: VReg, cases: &[(i64, Label)], default: Label) {
+    let values: Vec<i64> = cases.iter().map(|(val, _)| *val).collect();
+
+    if !is_dense(&values) {
+        // Comparison chain: a zero difference means a match.
+        for (val, label) in cases {
+            let imm = gen_imm(code, *val);
+            let tmp = new_reg();
+            code.push(IR::Sub {
+                dst: tmp,
+                lhs: r,
+                rhs: imm,
+            });
+            kill(code, imm);
+            code.push(IR::Unless {
+                lhs: tmp,
+                label: *label,
+            });
+            kill(code, tmp);
+        }
+        code.push(IR::Jmp { label: default });
+        return;
+    }
+
+    // Jump table indexed by the value minus the smallest case. Slots
+    // without a case go to the default label.
+    let min = *values.iter().min().unwrap();
+    let max = *values.iter().max().unwrap();
+    let mut targets = vec![default; (max - min + 1) as usize];
+    for (val, label) in cases {
+        targets[(val - min) as usize] = *label;
+    }
+
+    let imm = gen_imm(code, min);
+    let idx = new_reg();
+    code.push(IR::Sub {
+        dst: idx,
+        lhs: r,
+        rhs: imm,
+    });
+    kill(code, imm);
+    code.push(IR::JumpTable {
+        lhs: idx,
+        default,
+        targets,
+    });
+    kill(code, idx);
+}
+
 fn gen_stmt(code: &mut Vec<IR>, node: Node) {
     match node.ty {
         NodeType::If(cond, then, els_may) => {
             let r = gen_expr(code, *cond);
-            let x = Some(*LABEL.lock().unwrap());
-            *LABEL.lock().unwrap() += 1;
-            code.push(IR::new(IROp::Unless, r, x));
-            code.push(IR::new(IROp::Kill, r, None));
+            let x = new_label();
+            code.push(IR::Unless { lhs: r, label: x });
+            kill(code, r);
             gen_stmt(code, *then);
 
             if let Some(els) = els_may {
-                let y = Some(*LABEL.lock().unwrap());
-                *LABEL.lock().unwrap() += 1;
-                code.push(IR::new(IROp::Jmp, y, None));
-                code.push(IR::new(IROp::Label, x, None));
+                let y = new_label();
+                code.push(IR::Jmp { label: y });
+                code.push(IR::Label { label: x });
                 gen_stmt(code, *els);
-                code.push(IR::new(IROp::Label, y, None));
+                code.push(IR::Label { label: y });
             } else {
-                code.push(IR::new(IROp::Label, x, None));
+                code.push(IR::Label { label: x });
             }
         }
+        NodeType::Switch(cond, body, cases, has_default) => {
+            let r = gen_expr(code, *cond);
+            let labels = SwitchLabels {
+                cases: cases.iter().map(|val| (*val, new_label())).collect(),
+                default: if has_default { Some(new_label()) } else { None },
+                brk: new_label(),
+            };
+
+            let targets: Vec<(i64, Label)> =
+                cases.iter().map(|val| (*val, labels.cases[val])).collect();
+            let brk = labels.brk;
+            gen_dispatch(code, r, &targets, labels.default.unwrap_or(brk));
+            kill(code, r);
+
+            SWITCHES.lock().unwrap().push(labels);
+            gen_stmt(code, *body);
+            SWITCHES.lock().unwrap().pop();
+            code.push(IR::Label { label: brk });
+        }
+        NodeType::Case(_, val, body) => {
+            let label = SWITCHES.lock().unwrap().last().unwrap().cases[&val];
+            code.push(IR::Label { label });
+            gen_stmt(code, *body);
+        }
+        NodeType::Default(body) => {
+            let label = SWITCHES.lock().unwrap().last().unwrap().default.unwrap();
+            code.push(IR::Label { label });
+            gen_stmt(code, *body);
+        }
+        NodeType::Break => {
+            let label = SWITCHES.lock().unwrap().last().unwrap().brk;
+            code.push(IR::Jmp { label });
+        }
+        NodeType::Label(name, body) => {
+            code.push(IR::Label {
+                label: user_label(name),
+            });
+            gen_stmt(code, *body);
+        }
+        NodeType::Goto(name) => code.push(IR::Jmp {
+            label: user_label(name),
+        }),
         NodeType::Return(expr) => {
             let r = gen_expr(code, *expr);
-            code.push(IR::new(IROp::Return, r, None));
-            code.push(IR::new(IROp::Kill, r, None));
+            code.push(IR::Return { lhs: r });
+            kill(code, r);
         }
         NodeType::ExprStmt(expr) => {
             let r = gen_expr(code, *expr);
-            code.push(IR::new(IROp::Kill, r, None));
+            kill(code, r);
         }
         NodeType::CompStmt(stmts) => {
             for n in stmts {
                 gen_stmt(code, n);
             }
         }
+        NodeType::Null => (),
         e => panic!("unknown code: {:?}", e),
     }
 }
 
-pub fn gen_ir(node: Node) -> Vec<IR> {
+fn gen_arg_slot(code: &mut Vec<IR>, off: usize) -> VReg {
+    let r = new_reg();
+    code.push(IR::AddImm {
+        dst: r,
+        lhs: *BASE_REG.lock().unwrap(),
+        imm: Imm(off as i64),
+    });
+    r
+}
+
+fn gen_func(func: sema::Function) -> Function {
     let mut code = vec![];
+    *REGNO.lock().unwrap() = 1;
+    USER_LABELS.lock().unwrap().clear();
+    let base = *BASE_REG.lock().unwrap();
+    *ARG_SLOTS.lock().unwrap() = ArgSlots {
+        base: func.stacksize,
+        ..ArgSlots::default()
+    };
+
+    code.push(IR::Alloca {
+        lhs: base,
+        size: Imm(func.stacksize as i64),
+    });
+
+    // Spill register arguments into the parameters' stack slots.
+    for (i, param) in func.params.into_iter().enumerate() {
+        let ty = param.ctype.clone();
+        let r = gen_lval(&mut code, param);
+        code.push(store_arg_insn(&ty, r, Slot(i)));
+        kill(&mut code, r);
+    }
+
+    gen_stmt(&mut code, func.body);
+
+    // Falling off the end of a function returns 0.
+    let last = code.iter().rev().find(|ir| !matches!(ir, IR::Kill { .. }));
+    if !matches!(last, Some(IR::Return { .. })) {
+        let r = gen_imm(&mut code, 0);
+        code.push(IR::Return { lhs: r });
+        kill(&mut code, r);
+    }
+    kill(&mut code, base);
+
+    let spills = ARG_SLOTS.lock().unwrap().max;
+    code[0] = IR::Alloca {
+        lhs: base,
+        size: Imm(roundup(func.stacksize + spills, 16) as i64),
+    };
+
+    Function {
+        name: func.name,
+        irv: code,
+    }
+}
 
-    code.push(IR::new(IROp::Alloca, Some(*BASE_REG.lock().unwrap()), None));
-    gen_stmt(&mut code, node);
-    code[0].rhs = Some(*BPOFF.lock().unwrap());
-    code.push(IR::new(IROp::Kill, Some(*BASE_REG.lock().unwrap()), None));
-    code
+pub fn gen_ir(funcs: Vec<sema::Function>) -> Vec<Function> {
+    funcs.into_iter().map(gen_func).collect()
 }
@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+
+use crate::ir::{find_irinfo, Function, Imm, Label, OperandKind, Slot, VReg, Value, IR};
+use crate::sema::{Data, Global};
+
+// Reads the textual IR printed by dump_ir. A program is a list of globals
+// followed by functions:
+//
+//   .data g, 8, 8          ; name, size, alignment (also .rodata and .bss)
+//     .byte 1, 0, 0, 0
+//     .quad h              ; address of another global
+//
+//   main():
+//     ALLOCA r0, 16
+//   .L0:
+//     RET r1
+//
+// Everything after a `;` is a comment, and indentation is insignificant.
+pub fn parse_ir(src: &str) -> (Vec<Global>, Vec<Function>) {
+    let mut globals: Vec<Global> = vec![];
+    let mut fns: Vec<Function> = vec![];
+    // Where each global is declared
+    let mut decl_lines = vec![];
+    // Labels are assembler symbols, so they must be unique in the program.
+    let mut labels = HashSet::new();
+
+    for (i, line) in src.lines().enumerate() {
+        let lineno = i + 1;
+        let line = line.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (head, rest) = match line.split_once(char::is_whitespace) {
+            Some((head, rest)) => (head, rest.trim()),
+            None => (line, ""),
+        };
+
+        match head {
+            ".data" | ".rodata" | ".bss" => {
+                if !fns.is_empty() {
+                    panic!("global after functions at line {}", lineno);
+                }
+                globals.push(global(head, rest, lineno));
+                decl_lines.push(lineno);
+            }
+            ".byte" | ".quad" => {
+                let var = match globals.last_mut() {
+                    Some(var) if fns.is_empty() && var.data.is_some() => var,
+                    _ => panic!("{} outside of initialized data at line {}", head, lineno),
+                };
+                let data = var.data.as_mut().unwrap();
+                if head == ".quad" {
+                    data.push(Data::Label(symbol(rest, lineno)));
+                    continue;
+                }
+                let bytes = list(rest)
+                    .into_iter()
+                    .map(|b| match b.parse::<u8>() {
+                        Ok(b) => b,
+                        Err(_) => panic!("byte expected, but got {} at line {}", b, lineno),
+                    })
+                    .collect();
+                data.push(Data::Bytes(bytes));
+            }
+            _ if line.ends_with("():") => {
+                let name = symbol(&line[..line.len() - 3], lineno);
+                fns.push(Function { name, irv: vec![] });
+            }
+            _ => {
+                let f = match fns.last_mut() {
+                    Some(f) => f,
+                    None => panic!("instruction outside of a function at line {}", lineno),
+                };
+                let ir = insn(line, head, rest, lineno);
+                if let IR::Label { label } = ir {
+                    if !labels.insert(label) {
+                        panic!("label {} defined twice at line {}", label, lineno);
+                    }
+                }
+                f.irv.push(ir);
+            }
+        }
+    }
+
+    for (var, lineno) in globals.iter().zip(decl_lines) {
+        let len: usize = match var.data {
+            Some(ref data) => data
+                .iter()
+                .map(|d| match d {
+                    Data::Bytes(bytes) => bytes.len(),
+                    Data::Label(_) => 8,
+                })
+                .sum(),
+            None => continue,
+        };
+        if len != var.size {
+            panic!(
+                "{} has {} bytes of data, but its size is {} at line {}",
+                var.name, len, var.size, lineno
+            );
+        }
+    }
+    (globals, fns)
+}
+
+fn global(section: &str, rest: &str, lineno: usize) -> Global {
+    let fields = list(rest);
+    if fields.len() != 3 {
+        panic!("name, size and alignment expected at line {}", lineno);
+    }
+    Global {
+        name: symbol(fields[0], lineno),
+        size: number(fields[1], lineno) as usize,
+        align: number(fields[2], lineno) as usize,
+        data: if section == ".bss" {
+            None
+        } else {
+            Some(vec![])
+        },
+        readonly: section == ".rodata",
+    }
+}
+
+fn insn(line: &str, head: &str, rest: &str, lineno: usize) -> IR {
+    if let Some(label) = line.strip_suffix(':') {
+        let label = label_id(label, lineno);
+        return IR::Label { label };
+    }
+
+    let info = match find_irinfo(head) {
+        Some(info) => info,
+        None => panic!("unknown instruction {} at line {}", head, lineno),
+    };
+
+    let ops = operands(rest, head, lineno);
+    if ops.len() != info.operands.len() {
+        panic!(
+            "{} takes {} operands, but got {} at line {}",
+            head,
+            info.operands.len(),
+            ops.len(),
+            lineno
+        );
+    }
+
+    let values = info
+        .operands
+        .iter()
+        .zip(ops)
+        .map(|(kind, op)| match kind {
+            OperandKind::VReg => Value::VReg(reg(op, lineno)),
+            OperandKind::Imm => Value::Imm(Imm(number(op, lineno))),
+            OperandKind::Label => Value::Label(label_id(op, lineno)),
+            OperandKind::Slot => match number(op, lineno) {
+                n @ 0..=5 => Value::Slot(Slot(n as usize)),
+                n => panic!("argument slot {} out of range at line {}", n, lineno),
+            },
+            OperandKind::Symbol => Value::Symbol(symbol(op, lineno)),
+            OperandKind::Args => match op.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+                Some(args) => Value::Args(list(args).iter().map(|r| reg(r, lineno)).collect()),
+                None => panic!("argument list expected, but got {} at line {}", op, lineno),
+            },
+            OperandKind::Targets => match op.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                Some(labels) => {
+                    let labels = list(labels).iter().map(|l| label_id(l, lineno)).collect();
+                    Value::Targets(labels)
+                }
+                None => panic!("label list expected, but got {} at line {}", op, lineno),
+            },
+        })
+        .collect();
+    IR::build(info.name, values).unwrap()
+}
+
+// Splits operands at commas. A parenthesized or bracketed list is one
+// operand, and an argument list is split off the name in front of it.
+fn operands<'a>(s: &'a str, head: &str, lineno: usize) -> Vec<&'a str> {
+    let mut ops = vec![];
+    let mut start = 0;
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' if depth == 0 => {
+                if !s[start..i].trim().is_empty() {
+                    ops.push(s[start..i].trim());
+                }
+                start = i;
+                depth += 1;
+            }
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth == 0 => panic!("malformed operands of {} at line {}", head, lineno),
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                ops.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if depth != 0 {
+        panic!("malformed operands of {} at line {}", head, lineno);
+    }
+    if !s[start..].trim().is_empty() || !ops.is_empty() {
+        ops.push(s[start..].trim());
+    }
+    ops
+}
+
+// Comma-separated items
+fn list(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        return vec![];
+    }
+    s.split(',').map(str::trim).collect()
+}
+
+fn number(s: &str, lineno: usize) -> i64 {
+    match s.parse() {
+        Ok(val) => val,
+        Err(_) => panic!("number expected, but got {} at line {}", s, lineno),
+    }
+}
+
+fn reg(s: &str, lineno: usize) -> VReg {
+    match s.strip_prefix('r').and_then(|n| n.parse().ok()) {
+        Some(r) => VReg(r),
+        None => panic!("register expected, but got {} at line {}", s, lineno),
+    }
+}
+
+fn label_id(s: &str, lineno: usize) -> Label {
+    match s.strip_prefix(".L").and_then(|n| n.parse().ok()) {
+        Some(l) => Label(l),
+        None => panic!("label expected, but got {} at line {}", s, lineno),
+    }
+}
+
+// A global or function name. String literals are named .L.str0 and so on.
+fn symbol(s: &str, lineno: usize) -> String {
+    let valid = !s.is_empty()
+        && !s.starts_with(|c: char| c.is_ascii_digit())
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+    if !valid {
+        panic!("symbol expected, but got {} at line {}", s, lineno);
+    }
+    s.to_string()
+}
@@ -1,8 +1,28 @@
 pub mod codegen;
+pub mod dump;
+pub mod fmt;
+pub mod interp;
 pub mod ir;
+pub mod irparse;
 pub mod parse;
+pub mod preprocess;
 pub mod regalloc;
+pub mod sema;
 pub mod token;
+pub mod verify;
 
-const REGS_N: usize = 8;
-const REGS: [&str; REGS_N] = ["rdi", "rsi", "r10", "r11", "r12", "r13", "r14", "r15"];
+// rdi and rsi come last, so that they are only allocated once the
+// parameters they bring in have been stored.
+const REGS_N: usize = 9;
+const REGS: [&str; REGS_N] = [
+    "r10", "r11", "rbx", "r12", "r13", "r14", "r15", "rdi", "rsi",
+];
+const REGS8: [&str; REGS_N] = [
+    "r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b", "dil", "sil",
+];
+const REGS16: [&str; REGS_N] = [
+    "r10w", "r11w", "bx", "r12w", "r13w", "r14w", "r15w", "di", "si",
+];
+const REGS32: [&str; REGS_N] = [
+    "r10d", "r11d", "ebx", "r12d", "r13d", "r14d", "r15d", "edi", "esi",
+];
@@ -1,59 +1,125 @@
-use std::env;
+use std::{env, fs};
 
-use mona::codegen::gen_x86;
+use mona::codegen::{gen_x86, lower_two_address};
+use mona::dump::{dump_ast, dump_ast_json};
+use mona::fmt::format;
 use mona::ir::{dump_ir, gen_ir};
+use mona::irparse::parse_ir;
 use mona::parse::Node;
+use mona::preprocess::add_include_path;
 use mona::regalloc::alloc_regs;
+use mona::sema::sema;
 use mona::token::tokenize;
+use mona::verify::{verify, verify_allocated};
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: mona [-dump-ast[=json]] [-dump-ir1] [-dump-ir2] [-verify-ir] [-I<dir>] <code>"
+    );
+    eprintln!("       mona [-dump-ir1] [-dump-ir2] [-verify-ir] --from-ir <file>");
+    eprintln!("       mona fmt <code>");
+    std::process::exit(1)
+}
 
 fn main() {
-    let mut args = env::args();
-    let input: String;
+    let mut args = env::args().skip(1).peekable();
+
+    // Prints the code in the canonical layout instead of compiling it.
+    if args.peek().map(String::as_str) == Some("fmt") {
+        args.next();
+        match (args.next(), args.next()) {
+            (Some(input), None) => return print!("{}", format(input)),
+            _ => usage(),
+        }
+    }
+
+    let mut input = None;
+    let mut ast_format = None;
     let mut dump_ir1 = false;
     let mut dump_ir2 = false;
+    let mut verify_ir = false;
+    let mut from_ir = None;
 
-    if args.len() == 3 {
-        match args.nth(1).unwrap().as_str() {
-            "-dump-ir1" => {
-                dump_ir1 = true;
-                input = args.next().unwrap();
-            }
-            "-dump-ir2" => {
-                dump_ir2 = true;
-                input = args.next().unwrap();
-            }
-            _ => {
-                panic!("invalid flag");
-            }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-dump-ast" => ast_format = Some("tree"),
+            "-dump-ast=json" => ast_format = Some("json"),
+            "-dump-ir1" => dump_ir1 = true,
+            "-dump-ir2" => dump_ir2 = true,
+            "-verify-ir" => verify_ir = true,
+            "--from-ir" => match args.next() {
+                Some(path) => from_ir = Some(path),
+                None => usage(),
+            },
+            "-I" => match args.next() {
+                Some(dir) => add_include_path(dir),
+                None => usage(),
+            },
+            _ if arg.starts_with("-I") => add_include_path(arg[2..].to_string()),
+            _ if input.is_none() => input = Some(arg),
+            _ => usage(),
         }
-    } else {
-        if args.len() != 2 {
-            eprintln!("Usage: 9cc <code>");
-            return;
-        }
-        input = args.nth(1).unwrap();
     }
+    let (globals, mut fns) = match (input, from_ir) {
+        (Some(input), None) => {
+            // Tokenize and parse. The code given on the command line is
+            // treated as a file named "-" in the current directory.
+            let tokens = tokenize(input, "-");
+            let nodes = Node::parse(&tokens);
 
-    // Tokenize and parse.
-    let tokens = tokenize(input);
-    let node = Node::parse(&tokens);
+            // The AST is dumped as parsed, and nothing is compiled.
+            match ast_format {
+                Some("tree") => return dump_ast(&nodes),
+                Some(_) => return dump_ast_json(&nodes),
+                None => (),
+            }
 
-    let mut irv = gen_ir(node);
+            let prog = sema(nodes);
+            let fns = gen_ir(prog.funcs);
+            if verify_ir {
+                verify(&fns, "gen_ir");
+            }
+            (prog.globals, fns)
+        }
+        // IR read from a file has virtual registers, as after gen_ir.
+        (None, Some(path)) if ast_format.is_none() => {
+            let src = match fs::read_to_string(&path) {
+                Ok(src) => src,
+                Err(e) => panic!("cannot open {}: {}", path, e),
+            };
+            let (globals, fns) = parse_ir(&src);
+            if verify_ir {
+                verify(&fns, "parse_ir");
+            }
+            (globals, fns)
+        }
+        _ => usage(),
+    };
 
+    // IR dumps are in the syntax of --from-ir, so nothing else is printed.
     if dump_ir1 {
-        dump_ir(&irv);
+        dump_ir(&globals, &fns);
     }
 
-    alloc_regs(&mut irv);
+    for f in fns.iter_mut() {
+        alloc_regs(&mut f.irv);
+    }
+    if verify_ir {
+        verify_allocated(&fns, "alloc_regs");
+    }
 
     if dump_ir2 {
-        dump_ir(&irv);
+        dump_ir(&globals, &fns);
+    }
+    if dump_ir1 || dump_ir2 {
+        return;
     }
 
-    // Prologue
-    println!(".intel_syntax noprefix");
-    println!(".global main");
-    println!("main:");
-
-    gen_x86(irv);
+    for f in fns.iter_mut() {
+        lower_two_address(&mut f.irv);
+    }
+    if verify_ir {
+        verify_allocated(&fns, "lower_two_address");
+    }
+    gen_x86(&globals, fns);
 }
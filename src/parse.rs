@@ -1,151 +1,1314 @@
-use crate::token::{Token, TokenType};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Mutex,
+};
 
-fn expect(t: &Token, ty: TokenType, pos: &mut usize) {
-    if t.ty != ty {
-        panic!("expected {:?} but got {:?} at {}", ty, t.ty, *pos);
+use lazy_static::lazy_static;
+
+use crate::token::{self, intern, Comment, Span, Token, TokenType};
+
+// A cursor over a token stream which ends with an Eof token. Looking
+// past the end yields the Eof token, and bumping it is a no-op, so the
+// parse functions never index out of bounds.
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token]) -> Self {
+        assert!(
+            matches!(tokens.last(), Some(t) if t.ty == TokenType::Eof),
+            "token stream must end with Eof"
+        );
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> &'a Token {
+        self.peek_n(0)
+    }
+
+    // The token `n` tokens ahead of the current one.
+    fn peek_n(&self, n: usize) -> &'a Token {
+        let i = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[i]
+    }
+
+    fn bump(&mut self) -> &'a Token {
+        let t = self.peek();
+        if t.ty != TokenType::Eof {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn at_eof(&self) -> bool {
+        self.peek().ty == TokenType::Eof
+    }
+
+    fn expect(&mut self, ty: TokenType) {
+        let t = self.peek();
+        if t.ty != ty {
+            panic!("expected {} but got {} at line {}", ty, t, t.line);
+        }
+        self.bump();
+    }
+
+    fn eat(&mut self, ty: TokenType) -> bool {
+        if self.peek().ty != ty {
+            return false;
+        }
+        self.bump();
+        true
+    }
+
+    // Gives `node` the span from `start` to the last token consumed. A node
+    // ending in another source, as after an #include, only gets the span
+    // of its first token.
+    fn spanned(&self, start: &Token, mut node: Node) -> Node {
+        let end = &self.tokens[self.pos.saturating_sub(1)];
+        node.span = if start.src.as_ptr() == end.src.as_ptr() && start.span.start <= end.span.end {
+            Some(Span {
+                start: start.span.start,
+                end: end.span.end,
+            })
+        } else {
+            Some(start.span)
+        };
+        node.line = Some(start.line);
+        node
+    }
+}
+
+// Only variables, dereferences and members can be assigned to. Whether an
+// identifier names a variable is checked once names are resolved.
+fn check_lval(node: &Node, t: &Token) {
+    match node.ty {
+        NodeType::Ident(_)
+        | NodeType::Deref(_)
+        | NodeType::Dot(..)
+        | NodeType::Index(..)
+        | NodeType::Arrow(..) => (),
+        _ => panic!("expression is not assignable at line {}", t.line),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+pub const CONDITIONAL_LEVEL: u8 = 13;
+pub const ASSIGN_LEVEL: u8 = 14;
+pub const COMMA_LEVEL: u8 = 15;
+
+// Infix operators with their C precedence level, where a lower level binds
+// tighter. Levels 1 and 2 are the postfix and prefix operators, which are
+// parsed by postfix() and unary().
+const BINARY_OPS: [(TokenType, u8, Assoc); 31] = [
+    (TokenType::Mul, 3, Assoc::Left),
+    (TokenType::Div, 3, Assoc::Left),
+    (TokenType::Mod, 3, Assoc::Left),
+    (TokenType::Plus, 4, Assoc::Left),
+    (TokenType::Minus, 4, Assoc::Left),
+    (TokenType::Shl, 5, Assoc::Left),
+    (TokenType::Shr, 5, Assoc::Left),
+    (TokenType::Lt, 6, Assoc::Left),
+    (TokenType::Gt, 6, Assoc::Left),
+    (TokenType::Le, 6, Assoc::Left),
+    (TokenType::Ge, 6, Assoc::Left),
+    (TokenType::EqEq, 7, Assoc::Left),
+    (TokenType::Ne, 7, Assoc::Left),
+    (TokenType::And, 8, Assoc::Left),
+    (TokenType::Xor, 9, Assoc::Left),
+    (TokenType::Or, 10, Assoc::Left),
+    (TokenType::LogAnd, 11, Assoc::Left),
+    (TokenType::LogOr, 12, Assoc::Left),
+    (TokenType::Question, CONDITIONAL_LEVEL, Assoc::Right),
+    (TokenType::Equal, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::AddEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::SubEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::MulEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::DivEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::ModEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::ShlEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::ShrEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::AndEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::OrEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::XorEq, ASSIGN_LEVEL, Assoc::Right),
+    (TokenType::Comma, COMMA_LEVEL, Assoc::Left),
+];
+
+pub fn binary_op(ty: &TokenType) -> Option<(u8, Assoc)> {
+    BINARY_OPS
+        .iter()
+        .find(|(op, _, _)| op == ty)
+        .map(|(_, level, assoc)| (*level, *assoc))
+}
+
+// Whether there is a function definition: a name and a parenthesized
+// list followed by `{`, outside of any braces.
+fn has_func_def(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate() {
+        match t.ty {
+            TokenType::LeftBrace => depth += 1,
+            TokenType::RightBrace => depth -= 1,
+            TokenType::LeftParen if depth == 0 && i > 0 => {
+                if !matches!(tokens[i - 1].ty, TokenType::Ident(_)) {
+                    continue;
+                }
+                let mut parens = 0;
+                for (j, u) in tokens.iter().enumerate().skip(i) {
+                    match u.ty {
+                        TokenType::LeftParen => parens += 1,
+                        TokenType::RightParen => parens -= 1,
+                        TokenType::Eof => return false,
+                        _ => continue,
+                    }
+                    if parens == 0 {
+                        if tokens[j + 1].ty == TokenType::LeftBrace {
+                            return true;
+                        }
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
     }
-    *pos += 1;
+    false
 }
 
-fn consume(tokens: &Vec<Token>, ty: TokenType, pos: &mut usize) -> bool {
-    let t = &tokens[*pos];
-    if t.ty != ty {
-        return false;
+// Whether the token starts a type name.
+fn is_typename(t: &Token) -> bool {
+    use TokenType::*;
+    match t.ty {
+        Ident(ref name) => matches!(find_symbol(name), Some(Symbol::Typedef(_))),
+        Char | Short | Int | Long | Struct | Union | Enum => true,
+        _ => false,
+    }
+}
+
+lazy_static! {
+    static ref STRUCTS: Mutex<Vec<StructDef>> = Mutex::new(vec![]);
+    static ref SCOPES: Mutex<Vec<Scope>> = Mutex::new(vec![]);
+    static ref SWITCHES: Mutex<Vec<SwitchCases>> = Mutex::new(vec![]);
+    static ref LABELS: Mutex<FuncLabels> = Mutex::new(FuncLabels::default());
+}
+
+// Labels live in a per-function namespace. A goto may jump forward, so
+// its target is checked once the whole function body has been parsed.
+#[derive(Debug, Default)]
+struct FuncLabels {
+    defined: HashSet<String>,
+    gotos: Vec<String>,
+}
+
+// Case labels seen so far in the body of an enclosing switch statement.
+#[derive(Debug, Default)]
+struct SwitchCases {
+    cases: Vec<i64>,
+    has_default: bool,
+}
+
+// What an ordinary identifier denotes. The parser has to know which names
+// are types to tell declarations from expressions.
+#[derive(Debug, Clone)]
+enum Symbol {
+    Var,
+    Typedef(Type),
+    EnumConst(i64),
+}
+
+#[derive(Debug, Clone)]
+enum Tag {
+    Struct(usize),
+    Enum,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    symbols: HashMap<u32, Symbol>,
+    tags: HashMap<String, Tag>,
+}
+
+fn enter_scope() {
+    SCOPES.lock().unwrap().push(Scope::default());
+}
+
+fn leave_scope() {
+    SCOPES.lock().unwrap().pop();
+}
+
+// Names are looked up by their interned id.
+fn find_symbol(name: &token::Symbol) -> Option<Symbol> {
+    let scopes = SCOPES.lock().unwrap();
+    scopes
+        .iter()
+        .rev()
+        .find_map(|scope| scope.symbols.get(&name.id()).cloned())
+}
+
+fn add_symbol(name: &str, sym: Symbol) {
+    let mut scopes = SCOPES.lock().unwrap();
+    scopes.last_mut().unwrap().symbols.insert(intern(name), sym);
+}
+
+fn find_tag(name: &str, current_only: bool) -> Option<Tag> {
+    let scopes = SCOPES.lock().unwrap();
+    let n = if current_only { 1 } else { scopes.len() };
+    scopes
+        .iter()
+        .rev()
+        .take(n)
+        .find_map(|scope| scope.tags.get(name).cloned())
+}
+
+fn add_tag(name: String, tag: Tag) {
+    let mut scopes = SCOPES.lock().unwrap();
+    scopes.last_mut().unwrap().tags.insert(name, tag);
+}
+
+// Evaluates an integer constant expression.
+pub fn eval(node: &Node) -> i64 {
+    match node.ty {
+        NodeType::Num(val) | NodeType::EnumConst(_, val) => val,
+        NodeType::SizeofType(ref ty) => ty.size() as i64,
+        NodeType::Unary(ref op, ref expr) => {
+            let val = eval(expr);
+            match op {
+                TokenType::Minus => val.wrapping_neg(),
+                TokenType::Plus => val,
+                TokenType::Not => (val == 0) as i64,
+                TokenType::Tilde => !val,
+                _ => unreachable!(),
+            }
+        }
+        NodeType::Cast(ref expr) => {
+            let val = eval(expr);
+            match node.ctype.size() {
+                1 => val as i8 as i64,
+                2 => val as i16 as i64,
+                4 => val as i32 as i64,
+                _ => val,
+            }
+        }
+        NodeType::BinOp(TokenType::LogAnd, ref lhs, ref rhs) => {
+            (eval(lhs) != 0 && eval(rhs) != 0) as i64
+        }
+        NodeType::BinOp(TokenType::LogOr, ref lhs, ref rhs) => {
+            (eval(lhs) != 0 || eval(rhs) != 0) as i64
+        }
+        NodeType::BinOp(ref op, ref lhs, ref rhs) => {
+            let lhs = eval(lhs);
+            let rhs = eval(rhs);
+            match op {
+                TokenType::Plus => lhs.wrapping_add(rhs),
+                TokenType::Minus => lhs.wrapping_sub(rhs),
+                TokenType::Mul => lhs.wrapping_mul(rhs),
+                TokenType::Div if rhs == 0 => panic!("division by zero in constant expression"),
+                TokenType::Div => lhs.wrapping_div(rhs),
+                TokenType::Mod if rhs == 0 => panic!("division by zero in constant expression"),
+                TokenType::Mod => lhs.wrapping_rem(rhs),
+                TokenType::Shl => lhs.wrapping_shl(rhs as u32),
+                TokenType::Shr => lhs.wrapping_shr(rhs as u32),
+                TokenType::And => lhs & rhs,
+                TokenType::Or => lhs | rhs,
+                TokenType::Xor => lhs ^ rhs,
+                TokenType::EqEq => (lhs == rhs) as i64,
+                TokenType::Ne => (lhs != rhs) as i64,
+                TokenType::Lt => (lhs < rhs) as i64,
+                TokenType::Gt => (lhs > rhs) as i64,
+                TokenType::Le => (lhs <= rhs) as i64,
+                TokenType::Ge => (lhs >= rhs) as i64,
+                _ => panic!("not a constant expression: {:?}", op),
+            }
+        }
+        NodeType::Ternary(ref cond, ref then, ref els) => {
+            if eval(cond) != 0 {
+                eval(then)
+            } else {
+                eval(els)
+            }
+        }
+        _ => panic!("not a constant expression: {:?}", node.ty),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ctype {
+    Char,
+    Short,
+    Int,
+    Long,
+    Ptr(Box<Type>),
+    Ary(Box<Type>, usize),
+    Struct(usize), // Index into the struct table
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub ty: Type,
+    pub offset: usize,
+}
+
+// Layout of a struct or union. A struct is incomplete until its member list
+// has been seen, so that it can contain pointers to itself.
+#[derive(Debug, Clone)]
+pub struct StructDef {
+    pub is_union: bool,
+    pub members: Option<Vec<Member>>,
+    pub size: usize,
+    pub align: usize,
+}
+
+pub fn roundup(x: usize, align: usize) -> usize {
+    (x + align - 1) & !(align - 1)
+}
+
+fn new_struct(is_union: bool) -> usize {
+    let mut structs = STRUCTS.lock().unwrap();
+    structs.push(StructDef {
+        is_union,
+        members: None,
+        size: 0,
+        align: 1,
+    });
+    structs.len() - 1
+}
+
+// Lays out members following the C rules: each member is placed at the next
+// offset aligned for its type, and the whole is padded to its alignment.
+fn define_struct(id: usize, members: Vec<(String, Type)>) {
+    let is_union = STRUCTS.lock().unwrap()[id].is_union;
+    let mut size = 0;
+    let mut align = 1;
+    let mut laid_out = vec![];
+
+    for (name, ty) in members {
+        if laid_out.iter().any(|m: &Member| m.name == name) {
+            panic!("duplicate member: {}", name);
+        }
+
+        let offset = if is_union {
+            0
+        } else {
+            roundup(size, ty.align())
+        };
+        size = size.max(offset + ty.size());
+        align = align.max(ty.align());
+        laid_out.push(Member { name, ty, offset });
+    }
+
+    let def = &mut STRUCTS.lock().unwrap()[id];
+    def.members = Some(laid_out);
+    def.size = roundup(size, align);
+    def.align = align;
+}
+
+pub fn struct_def(id: usize) -> StructDef {
+    STRUCTS.lock().unwrap()[id].clone()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Type {
+    pub ty: Ctype,
+}
+
+impl Type {
+    fn new(ty: Ctype) -> Self {
+        Self { ty }
+    }
+
+    pub fn char() -> Self {
+        Self::new(Ctype::Char)
+    }
+
+    pub fn short() -> Self {
+        Self::new(Ctype::Short)
+    }
+
+    pub fn int() -> Self {
+        Self::new(Ctype::Int)
+    }
+
+    pub fn long() -> Self {
+        Self::new(Ctype::Long)
+    }
+
+    pub fn ptr_to(base: Type) -> Self {
+        Self::new(Ctype::Ptr(Box::new(base)))
+    }
+
+    pub fn array_of(base: Type, len: usize) -> Self {
+        Self::new(Ctype::Ary(Box::new(base), len))
+    }
+
+    pub fn size(&self) -> usize {
+        match self.ty {
+            Ctype::Char => 1,
+            Ctype::Short => 2,
+            Ctype::Int => 4,
+            Ctype::Long | Ctype::Ptr(_) => 8,
+            Ctype::Ary(ref base, len) => base.size() * len,
+            Ctype::Struct(id) => {
+                let def = struct_def(id);
+                if def.members.is_none() {
+                    panic!("incomplete type");
+                }
+                def.size
+            }
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        match self.ty {
+            Ctype::Ary(ref base, _) => base.align(),
+            Ctype::Struct(id) => struct_def(id).align,
+            _ => self.size(),
+        }
+    }
+
+    pub fn is_ptr(&self) -> bool {
+        matches!(self.ty, Ctype::Ptr(_))
+    }
+
+    pub fn is_struct(&self) -> bool {
+        matches!(self.ty, Ctype::Struct(_))
+    }
+
+    pub fn pointee(&self) -> Option<&Type> {
+        match self.ty {
+            Ctype::Ptr(ref base) => Some(base),
+            _ => None,
+        }
+    }
+
+    // Members of a struct or union type. For a union only the first member
+    // takes part in initialization.
+    pub fn members(&self) -> Option<Vec<Member>> {
+        match self.ty {
+            Ctype::Struct(id) => match struct_def(id).members {
+                Some(members) => Some(members),
+                None => panic!("incomplete type"),
+            },
+            _ => None,
+        }
+    }
+
+    pub fn member(&self, name: &str) -> Option<Member> {
+        self.members()?.into_iter().find(|m| m.name == name)
+    }
+}
+
+// A type as it would be spelled in a declaration, with the struct table
+// index standing in for a tag.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ty {
+            Ctype::Char => f.write_str("char"),
+            Ctype::Short => f.write_str("short"),
+            Ctype::Int => f.write_str("int"),
+            Ctype::Long => f.write_str("long"),
+            Ctype::Ptr(ref base) => write!(f, "{}*", base),
+            Ctype::Ary(..) => {
+                // int a[2][3] is an array of two arrays of three ints.
+                let mut ty = self;
+                let mut dims = String::new();
+                while let Ctype::Ary(ref base, len) = ty.ty {
+                    dims += &format!("[{}]", len);
+                    ty = base;
+                }
+                write!(f, "{}{}", ty, dims)
+            }
+            Ctype::Struct(id) if struct_def(id).is_union => write!(f, "union #{}", id),
+            Ctype::Struct(id) => write!(f, "struct #{}", id),
+        }
     }
-    *pos += 1;
-    true
 }
 
 #[derive(Debug, Clone)]
 pub enum NodeType {
-    Num(i32),                                    // Number literal
-    Ident(String),                               // Identifier
-    BinOp(TokenType, Box<Node>, Box<Node>),      // left-hand, right-hand
-    If(Box<Node>, Box<Node>, Option<Box<Node>>), // condition, then, else
-    Return(Box<Node>),                           // Return statement
-    ExprStmt(Box<Node>),                         // Expression statement
-    CompStmt(Vec<Node>),                         // Compound statement
+    Num(i64),                                     // Number literal
+    Str(Vec<u8>),                                 // String literal
+    Ident(String),                                // Identifier
+    Gvar(String),                                 // Global variable (label)
+    Call(String, Vec<Node>),                      // Function call
+    Lvar(usize),                                  // Local variable (offset)
+    Vardef(String, Option<Box<Node>>),            // Variable definition
+    Cast(Box<Node>),                              // Type conversion
+    Addr(Box<Node>),                              // Address-of (&)
+    Deref(Box<Node>),                             // Pointer dereference (*)
+    PostInc(Box<Node>),                           // Postfix ++
+    PostDec(Box<Node>),                           // Postfix --
+    PreInc(Box<Node>),                            // Prefix ++
+    PreDec(Box<Node>),                            // Prefix --
+    Unary(TokenType, Box<Node>),                  // Prefix -, +, ! or ~
+    Sizeof(Box<Node>),                            // sizeof expression
+    SizeofType(Type),                             // sizeof(type)
+    EnumConst(String, i64),                       // Enumerator
+    Index(Box<Node>, Box<Node>),                  // Array subscript
+    Dot(Box<Node>, String),                       // Struct member access
+    Arrow(Box<Node>, String),                     // Member access through a pointer
+    BinOp(TokenType, Box<Node>, Box<Node>),       // left-hand, right-hand
+    Ternary(Box<Node>, Box<Node>, Box<Node>),     // condition, then, else
+    If(Box<Node>, Box<Node>, Option<Box<Node>>),  // condition, then, else
+    Switch(Box<Node>, Box<Node>, Vec<i64>, bool), // condition, body, cases, has default
+    Case(Box<Node>, i64, Box<Node>),              // case label: expression, value, body
+    Default(Box<Node>),                           // default label
+    Break,                                        // Break statement
+    Null,                                         // Null statement (;)
+    Label(String, Box<Node>),                     // Labeled statement
+    Goto(String),                                 // Goto statement
+    Return(Box<Node>),                            // Return statement
+    ExprStmt(Box<Node>),                          // Expression statement
+    CompStmt(Vec<Node>),                          // Compound statement
+    InitList(Vec<Node>),                          // Brace-enclosed initializer
+    Typedef(String),                              // typedef declaration
+    TypeDecl,                                     // Declaration of just a type
+    Func(String, Vec<Node>, Box<Node>),           // name, parameters, body
 }
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub ty: NodeType,
+    pub ctype: Type,
+    // Where the node was parsed from, unless it was made up later.
+    pub span: Option<Span>,
+    // The line of its first token, for diagnostics after parsing
+    pub line: Option<usize>,
+    // How the type of a declaration was written
+    pub syntax: Option<Box<DeclSyntax>>,
+    // Comments around the node, attached when formatting
+    pub comments: Vec<(Attach, Comment)>,
+}
+
+// A declaration's type as written: a type specifier, then pointers and
+// array dimensions around the declared name. Sema only needs the resulting
+// Type, but the formatter prints this back.
+#[derive(Debug, Clone)]
+pub struct DeclSyntax {
+    pub spec: TypeSpec,
+    pub ptrs: usize,
+    pub dims: Vec<Node>,
+}
+
+impl DeclSyntax {
+    fn new(spec: TypeSpec) -> Self {
+        Self {
+            spec,
+            ptrs: 0,
+            dims: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeSpec {
+    Char,
+    Short,
+    Int,
+    Long,
+    Typedef(String),
+    // Members are Vardef nodes, and are only present where the body is.
+    Struct(bool, Option<String>, Option<Vec<Node>>), // is union, tag, members
+    Enum(Option<String>, Option<Vec<(String, Option<Node>)>>), // tag, enumerators
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attach {
+    Leading,  // On lines before the node
+    Trailing, // After the node on its last line
+    Closing,  // Before the closing brace of a block
 }
 
 impl Node {
-    fn new(op: NodeType) -> Self {
-        Self { ty: op }
+    pub fn new(op: NodeType) -> Self {
+        Self::with_type(op, Type::int())
     }
 
-    fn term(tokens: &Vec<Token>, pos: &mut usize) -> Self {
-        let t = &tokens[*pos];
-        *pos += 1;
+    pub fn with_type(op: NodeType, ctype: Type) -> Self {
+        Self {
+            ty: op,
+            ctype,
+            span: None,
+            line: None,
+            syntax: None,
+            comments: vec![],
+        }
+    }
+
+    fn term(p: &mut Parser) -> Self {
+        let t = p.bump();
         match t.ty {
-            TokenType::Num(val) => Self::new(NodeType::Num(val)),
-            TokenType::Ident(ref name) => Self::new(NodeType::Ident(name.to_string())),
+            TokenType::Num(val) => {
+                // A literal which doesn't fit in int is a long.
+                let ty = if i32::try_from(val).is_ok() {
+                    Type::int()
+                } else {
+                    Type::long()
+                };
+                Self::with_type(NodeType::Num(val), ty)
+            }
+            TokenType::Str(ref bytes) => Self::new(NodeType::Str(bytes.clone())),
+            TokenType::Ident(ref name) => {
+                if let Some(Symbol::EnumConst(val)) = find_symbol(name) {
+                    return Self::new(NodeType::EnumConst(name.to_string(), val));
+                }
+                if !p.eat(TokenType::LeftParen) {
+                    return Self::new(NodeType::Ident(name.to_string()));
+                }
+
+                let mut args = vec![];
+                if p.eat(TokenType::RightParen) {
+                    return Self::new(NodeType::Call(name.to_string(), args));
+                }
+                args.push(Self::assign(p));
+                while p.eat(TokenType::Comma) {
+                    args.push(Self::assign(p));
+                }
+                p.expect(TokenType::RightParen);
+                Self::new(NodeType::Call(name.to_string(), args))
+            }
             TokenType::LeftParen => {
-                let node = Self::assign(tokens, pos);
-                expect(&tokens[*pos], TokenType::RightParen, pos);
+                let node = Self::expr(p);
+                p.expect(TokenType::RightParen);
                 node
             }
-            _ => panic!("number expected, but got {}", t.input),
+            _ => panic!("number expected, but got {}", t),
         }
     }
 
-    fn mul(tokens: &Vec<Token>, pos: &mut usize) -> Self {
-        let mut lhs = Self::term(tokens, pos);
+    fn postfix(p: &mut Parser) -> Self {
+        let start = p.peek();
+        let term = Self::term(p);
+        let mut lhs = p.spanned(start, term);
 
         loop {
-            if tokens.len() == *pos {
-                return lhs;
+            if p.eat(TokenType::LeftBracket) {
+                let index = Self::expr(p);
+                p.expect(TokenType::RightBracket);
+                let node = NodeType::Index(Box::new(lhs), Box::new(index));
+                lhs = p.spanned(start, Self::new(node));
+                continue;
+            }
+
+            if p.eat(TokenType::Dot) {
+                let name = Self::ident(p);
+                lhs = p.spanned(start, Self::new(NodeType::Dot(Box::new(lhs), name)));
+                continue;
+            }
+
+            if p.eat(TokenType::Arrow) {
+                let name = Self::ident(p);
+                lhs = p.spanned(start, Self::new(NodeType::Arrow(Box::new(lhs), name)));
+                continue;
             }
 
-            let op = tokens[*pos].ty.clone();
-            if op != TokenType::Mul && op != TokenType::Div {
-                return lhs;
+            let t = p.peek();
+            if p.eat(TokenType::Inc) {
+                check_lval(&lhs, t);
+                lhs = p.spanned(start, Self::new(NodeType::PostInc(Box::new(lhs))));
+                continue;
             }
-            *pos += 1;
-            lhs = Self::new(NodeType::BinOp(
-                op,
-                Box::new(lhs),
-                Box::new(Self::term(tokens, pos)),
-            ));
+
+            let t = p.peek();
+            if p.eat(TokenType::Dec) {
+                check_lval(&lhs, t);
+                lhs = p.spanned(start, Self::new(NodeType::PostDec(Box::new(lhs))));
+                continue;
+            }
+            return lhs;
+        }
+    }
+
+    fn unary(p: &mut Parser) -> Self {
+        let start = p.peek();
+        if p.eat(TokenType::Mul) {
+            let node = Self::new(NodeType::Deref(Box::new(Self::unary(p))));
+            return p.spanned(start, node);
+        }
+        if p.eat(TokenType::And) {
+            let node = Self::new(NodeType::Addr(Box::new(Self::unary(p))));
+            return p.spanned(start, node);
+        }
+        if p.eat(TokenType::Sizeof) {
+            let node = Self::sizeof(p);
+            return p.spanned(start, node);
+        }
+
+        for op in [
+            TokenType::Minus,
+            TokenType::Plus,
+            TokenType::Not,
+            TokenType::Tilde,
+        ] {
+            if p.eat(op.clone()) {
+                let node = NodeType::Unary(op, Box::new(Self::unary(p)));
+                return p.spanned(start, Self::new(node));
+            }
+        }
+
+        let t = p.peek();
+        if p.eat(TokenType::Inc) {
+            let expr = Self::unary(p);
+            check_lval(&expr, t);
+            return p.spanned(start, Self::new(NodeType::PreInc(Box::new(expr))));
         }
+        if p.eat(TokenType::Dec) {
+            let expr = Self::unary(p);
+            check_lval(&expr, t);
+            return p.spanned(start, Self::new(NodeType::PreDec(Box::new(expr))));
+        }
+        Self::postfix(p)
     }
 
-    fn expr(tokens: &Vec<Token>, pos: &mut usize) -> Self {
-        let mut lhs = Self::mul(tokens, pos);
+    // Parses operators of precedence `level` or tighter, climbing the
+    // BINARY_OPS table. The operand of a left-associative operator must
+    // bind tighter than the operator itself, while that of a
+    // right-associative one may be another operator of the same level.
+    fn binary(p: &mut Parser, level: u8) -> Self {
+        let start = p.peek();
+        let mut lhs = Self::unary(p);
 
         loop {
-            if tokens.len() == *pos {
-                return lhs;
+            let t = p.peek();
+            let (op_level, assoc) = match binary_op(&t.ty) {
+                Some((op_level, assoc)) if op_level <= level => (op_level, assoc),
+                _ => return lhs,
+            };
+            p.bump();
+            let next = match assoc {
+                Assoc::Left => op_level - 1,
+                Assoc::Right => op_level,
+            };
+
+            // The middle operand of ?: is parsed as if parenthesized.
+            if t.ty == TokenType::Question {
+                let then = Self::expr(p);
+                p.expect(TokenType::Colon);
+                let els = Self::binary(p, next);
+                let node = Self::new(NodeType::Ternary(
+                    Box::new(lhs),
+                    Box::new(then),
+                    Box::new(els),
+                ));
+                lhs = p.spanned(start, node);
+                continue;
+            }
+
+            if op_level == ASSIGN_LEVEL {
+                check_lval(&lhs, t);
+            }
+            let rhs = Self::binary(p, next);
+            let node = Self::new(NodeType::BinOp(t.ty.clone(), Box::new(lhs), Box::new(rhs)));
+            lhs = p.spanned(start, node);
+        }
+    }
+
+    fn conditional(p: &mut Parser) -> Self {
+        Self::binary(p, CONDITIONAL_LEVEL)
+    }
+
+    fn assign(p: &mut Parser) -> Self {
+        Self::binary(p, ASSIGN_LEVEL)
+    }
+
+    // The comma operator evaluates its operands in order and yields the
+    // value of the last one.
+    fn expr(p: &mut Parser) -> Self {
+        Self::binary(p, COMMA_LEVEL)
+    }
+
+    // Parses a type specifier, returning the type and how it was written.
+    fn decl_type(p: &mut Parser) -> Option<(Type, DeclSyntax)> {
+        let (ty, spec) = match p.peek().ty {
+            TokenType::Ident(ref name) => match find_symbol(name) {
+                Some(Symbol::Typedef(ty)) => (ty, TypeSpec::Typedef(name.to_string())),
+                _ => return None,
+            },
+            TokenType::Char => (Type::char(), TypeSpec::Char),
+            TokenType::Short => (Type::short(), TypeSpec::Short),
+            TokenType::Int => (Type::int(), TypeSpec::Int),
+            TokenType::Long => (Type::long(), TypeSpec::Long),
+            TokenType::Struct => {
+                p.bump();
+                let (ty, spec) = Self::struct_type(p, false);
+                return Some((ty, DeclSyntax::new(spec)));
+            }
+            TokenType::Union => {
+                p.bump();
+                let (ty, spec) = Self::struct_type(p, true);
+                return Some((ty, DeclSyntax::new(spec)));
+            }
+            TokenType::Enum => {
+                p.bump();
+                let (ty, spec) = Self::enum_type(p);
+                return Some((ty, DeclSyntax::new(spec)));
+            }
+            _ => return None,
+        };
+        p.bump();
+        Some((ty, DeclSyntax::new(spec)))
+    }
+
+    fn expect_decl_type(p: &mut Parser) -> (Type, DeclSyntax) {
+        match Self::decl_type(p) {
+            Some(decl) => decl,
+            None => panic!("typename expected, but got {}", p.peek()),
+        }
+    }
+
+    fn struct_type(p: &mut Parser, is_union: bool) -> (Type, TypeSpec) {
+        let mut tag = None;
+        if let TokenType::Ident(ref name) = p.peek().ty {
+            tag = Some(name.to_string());
+            p.bump();
+        }
+
+        // A struct body always declares a new type in the current scope,
+        // but a bare reference may name a struct of an enclosing scope.
+        let has_body = p.eat(TokenType::LeftBrace);
+        let existing = tag.as_ref().and_then(|tag| find_tag(tag, has_body));
+
+        let id = match (existing, has_body) {
+            (Some(Tag::Struct(id)), _) if struct_def(id).is_union == is_union => {
+                if has_body && struct_def(id).members.is_some() {
+                    panic!("redefinition of {}", tag.unwrap());
+                }
+                id
+            }
+            (Some(_), _) => panic!("use of {} with tag type that does not match", tag.unwrap()),
+            (None, false) if tag.is_none() => {
+                panic!("struct body expected, but got {}", p.peek())
+            }
+            (None, _) => {
+                let id = new_struct(is_union);
+                if let Some(ref tag) = tag {
+                    add_tag(tag.clone(), Tag::Struct(id));
+                }
+                id
+            }
+        };
+
+        let mut nodes = None;
+        if has_body {
+            let mut members = vec![];
+            while !p.eat(TokenType::RightBrace) {
+                let start = p.peek();
+                let (ty, mut syntax) = Self::expect_decl_type(p);
+                let ty = Self::ptr_type(p, ty, &mut syntax);
+                let name = Self::ident(p);
+                let ty = Self::array_type(p, ty, &mut syntax);
+                p.expect(TokenType::Semicolon);
+                let mut node = Self::with_type(NodeType::Vardef(name, None), ty);
+                node.syntax = Some(Box::new(syntax));
+                members.push(p.spanned(start, node));
+            }
+            let layout = members.iter().map(|m| match m.ty {
+                NodeType::Vardef(ref name, _) => (name.clone(), m.ctype.clone()),
+                _ => unreachable!(),
+            });
+            define_struct(id, layout.collect());
+            nodes = Some(members);
+        }
+        let spec = TypeSpec::Struct(is_union, tag, nodes);
+        (Type::new(Ctype::Struct(id)), spec)
+    }
+
+    // Enumerators are int constants in the enclosing scope, and an enum
+    // type is just int.
+    fn enum_type(p: &mut Parser) -> (Type, TypeSpec) {
+        let mut tag = None;
+        if let TokenType::Ident(ref name) = p.peek().ty {
+            tag = Some(name.to_string());
+            p.bump();
+        }
+
+        if !p.eat(TokenType::LeftBrace) {
+            match tag {
+                Some(ref tag) => match find_tag(tag, false) {
+                    Some(Tag::Enum) => (),
+                    Some(_) => panic!("use of {} with tag type that does not match", tag),
+                    None => panic!("undefined enum: {}", tag),
+                },
+                None => panic!("enum body expected, but got {}", p.peek()),
+            }
+            return (Type::int(), TypeSpec::Enum(tag, None));
+        }
+
+        if let Some(ref tag) = tag {
+            if find_tag(tag, true).is_some() {
+                panic!("redefinition of {}", tag);
+            }
+            add_tag(tag.clone(), Tag::Enum);
+        }
+
+        let mut items = vec![];
+        let mut val = 0;
+        while !p.eat(TokenType::RightBrace) {
+            let name = Self::ident(p);
+            let mut expr = None;
+            if p.eat(TokenType::Equal) {
+                let node = Self::conditional(p);
+                val = eval(&node);
+                expr = Some(node);
             }
+            add_symbol(&name, Symbol::EnumConst(val));
+            items.push((name, expr));
+            val += 1;
 
-            let op = tokens[*pos].ty.clone();
-            if op != TokenType::Plus && op != TokenType::Minus {
-                return lhs;
+            if !p.eat(TokenType::Comma) {
+                p.expect(TokenType::RightBrace);
+                break;
             }
-            *pos += 1;
-            let rhs = Self::mul(tokens, pos);
-            lhs = Self::new(NodeType::BinOp(op, Box::new(lhs), Box::new(rhs)));
         }
+        (Type::int(), TypeSpec::Enum(tag, Some(items)))
     }
 
-    fn assign(tokens: &Vec<Token>, pos: &mut usize) -> Self {
-        let lhs = Self::expr(tokens, pos);
-        if consume(tokens, TokenType::Equal, pos) {
-            return Self::new(NodeType::BinOp(
-                TokenType::Equal,
-                Box::new(lhs),
-                Box::new(Self::expr(tokens, pos)),
-            ));
+    fn ptr_type(p: &mut Parser, mut ty: Type, syntax: &mut DeclSyntax) -> Type {
+        while p.eat(TokenType::Mul) {
+            ty = Type::ptr_to(ty);
+            syntax.ptrs += 1;
         }
-        lhs
+        ty
     }
 
-    fn stmt(tokens: &Vec<Token>, pos: &mut usize) -> Self {
-        match tokens[*pos].ty {
+    fn array_type(p: &mut Parser, ty: Type, syntax: &mut DeclSyntax) -> Type {
+        let mut lens = vec![];
+        while p.eat(TokenType::LeftBracket) {
+            let dim = Self::conditional(p);
+            let len = eval(&dim);
+            if len < 0 {
+                panic!("array has negative size: {}", len);
+            }
+            lens.push(len as usize);
+            syntax.dims.push(dim);
+            p.expect(TokenType::RightBracket);
+        }
+
+        // int a[2][3] is an array of two arrays of three ints.
+        lens.into_iter().rev().fold(ty, Type::array_of)
+    }
+
+    fn sizeof(p: &mut Parser) -> Self {
+        if p.peek().ty == TokenType::LeftParen && is_typename(p.peek_n(1)) {
+            p.bump();
+            let (ty, mut syntax) = Self::decl_type(p).unwrap();
+            let ty = Self::ptr_type(p, ty, &mut syntax);
+            let ty = Self::array_type(p, ty, &mut syntax);
+            p.expect(TokenType::RightParen);
+            let mut node = Self::with_type(NodeType::SizeofType(ty), Type::long());
+            node.syntax = Some(Box::new(syntax));
+            return node;
+        }
+        Self::new(NodeType::Sizeof(Box::new(Self::unary(p))))
+    }
+
+    fn ident(p: &mut Parser) -> String {
+        let t = p.peek();
+        let name = match t.ty {
+            TokenType::Ident(ref name) => name.to_string(),
+            _ => panic!("identifier expected, but got {}", t),
+        };
+        p.bump();
+        name
+    }
+
+    fn initializer(p: &mut Parser) -> Self {
+        let start = p.peek();
+        if !p.eat(TokenType::LeftBrace) {
+            return Self::assign(p);
+        }
+
+        let mut elems = vec![];
+        while !p.eat(TokenType::RightBrace) {
+            elems.push(Self::initializer(p));
+            if !p.eat(TokenType::Comma) {
+                p.expect(TokenType::RightBrace);
+                break;
+            }
+        }
+        p.spanned(start, Self::new(NodeType::InitList(elems)))
+    }
+
+    fn decl(p: &mut Parser, ty: Type, mut syntax: DeclSyntax) -> Self {
+        let ty = Self::ptr_type(p, ty, &mut syntax);
+        let name = Self::ident(p);
+        Self::vardef(p, ty, syntax, name)
+    }
+
+    // The rest of a variable definition after its name.
+    fn vardef(p: &mut Parser, ty: Type, mut syntax: DeclSyntax, name: String) -> Self {
+        let ty = Self::array_type(p, ty, &mut syntax);
+        add_symbol(&name, Symbol::Var);
+
+        let mut init = None;
+        if p.eat(TokenType::Equal) {
+            init = Some(Box::new(Self::initializer(p)));
+        }
+        p.expect(TokenType::Semicolon);
+        let mut node = Self::with_type(NodeType::Vardef(name, init), ty);
+        node.syntax = Some(Box::new(syntax));
+        node
+    }
+
+    fn param(p: &mut Parser) -> Self {
+        let start = p.peek();
+        let (ty, mut syntax) = Self::expect_decl_type(p);
+        let ty = Self::ptr_type(p, ty, &mut syntax);
+        let name = Self::ident(p);
+        add_symbol(&name, Symbol::Var);
+        let mut node = Self::with_type(NodeType::Vardef(name, None), ty);
+        node.syntax = Some(Box::new(syntax));
+        p.spanned(start, node)
+    }
+
+    // typedef int *intptr; makes `intptr` a type name in the current scope.
+    fn typedef(p: &mut Parser) -> Self {
+        let (ty, mut syntax) = Self::expect_decl_type(p);
+        let ty = Self::ptr_type(p, ty, &mut syntax);
+        let name = Self::ident(p);
+        let ty = Self::array_type(p, ty, &mut syntax);
+        p.expect(TokenType::Semicolon);
+        add_symbol(&name, Symbol::Typedef(ty.clone()));
+        let mut node = Self::with_type(NodeType::Typedef(name), ty);
+        node.syntax = Some(Box::new(syntax));
+        node
+    }
+
+    // A declaration without a declarator, like `struct a { int x; };`
+    fn type_decl(ty: Type, syntax: DeclSyntax) -> Self {
+        let mut node = Self::with_type(NodeType::TypeDecl, ty);
+        node.syntax = Some(Box::new(syntax));
+        node
+    }
+
+    fn stmt(p: &mut Parser) -> Self {
+        let start = p.peek();
+        let node = Self::unspanned_stmt(p);
+        p.spanned(start, node)
+    }
+
+    fn unspanned_stmt(p: &mut Parser) -> Self {
+        if p.eat(TokenType::Typedef) {
+            return Self::typedef(p);
+        }
+
+        // A label is an identifier followed by a colon, even if it is
+        // also a type name.
+        if let TokenType::Ident(ref name) = p.peek().ty {
+            if p.peek_n(1).ty == TokenType::Colon {
+                p.bump();
+                p.bump();
+                if !LABELS.lock().unwrap().defined.insert(name.to_string()) {
+                    panic!("redefinition of label '{}'", name);
+                }
+                let body = Self::stmt(p);
+                return Self::new(NodeType::Label(name.to_string(), Box::new(body)));
+            }
+        }
+
+        if let Some((ty, syntax)) = Self::decl_type(p) {
+            if p.eat(TokenType::Semicolon) {
+                return Self::type_decl(ty, syntax);
+            }
+            return Self::decl(p, ty, syntax);
+        }
+
+        match p.peek().ty {
+            TokenType::Semicolon => {
+                p.bump();
+                Self::new(NodeType::Null)
+            }
+            TokenType::LeftBrace => {
+                p.bump();
+                Self::compound_stmt(p)
+            }
             TokenType::If => {
                 let mut els = None;
-                *pos += 1;
-                expect(&tokens[*pos], TokenType::LeftParen, pos);
-                let cond = Self::assign(tokens, pos);
-                expect(&tokens[*pos], TokenType::RightParen, pos);
-                let then = Self::stmt(tokens, pos);
-                if consume(tokens, TokenType::Else, pos) {
-                    els = Some(Box::new(Self::stmt(tokens, pos)));
+                p.bump();
+                p.expect(TokenType::LeftParen);
+                let cond = Self::expr(p);
+                p.expect(TokenType::RightParen);
+                let then = Self::stmt(p);
+                if p.eat(TokenType::Else) {
+                    els = Some(Box::new(Self::stmt(p)));
                 }
                 Self::new(NodeType::If(Box::new(cond), Box::new(then), els))
             }
+            TokenType::Switch => {
+                p.bump();
+                p.expect(TokenType::LeftParen);
+                let cond = Self::expr(p);
+                p.expect(TokenType::RightParen);
+
+                SWITCHES.lock().unwrap().push(SwitchCases::default());
+                let body = Self::stmt(p);
+                let sw = SWITCHES.lock().unwrap().pop().unwrap();
+                Self::new(NodeType::Switch(
+                    Box::new(cond),
+                    Box::new(body),
+                    sw.cases,
+                    sw.has_default,
+                ))
+            }
+            TokenType::Case => {
+                p.bump();
+                let expr = Self::conditional(p);
+                let val = eval(&expr);
+                p.expect(TokenType::Colon);
+                match SWITCHES.lock().unwrap().last_mut() {
+                    Some(sw) if sw.cases.contains(&val) => panic!("duplicate case value: {}", val),
+                    Some(sw) => sw.cases.push(val),
+                    None => panic!("'case' statement not in switch statement"),
+                }
+                let body = Self::stmt(p);
+                Self::new(NodeType::Case(Box::new(expr), val, Box::new(body)))
+            }
+            TokenType::Default => {
+                p.bump();
+                p.expect(TokenType::Colon);
+                match SWITCHES.lock().unwrap().last_mut() {
+                    Some(sw) if sw.has_default => panic!("multiple default labels in one switch"),
+                    Some(sw) => sw.has_default = true,
+                    None => panic!("'default' statement not in switch statement"),
+                }
+                let body = Self::stmt(p);
+                Self::new(NodeType::Default(Box::new(body)))
+            }
+            TokenType::Break => {
+                p.bump();
+                p.expect(TokenType::Semicolon);
+                if SWITCHES.lock().unwrap().is_empty() {
+                    panic!("'break' statement not in switch statement");
+                }
+                Self::new(NodeType::Break)
+            }
+            TokenType::Goto => {
+                p.bump();
+                let name = Self::ident(p);
+                p.expect(TokenType::Semicolon);
+                LABELS.lock().unwrap().gotos.push(name.clone());
+                Self::new(NodeType::Goto(name))
+            }
             TokenType::Return => {
-                *pos += 1;
-                let expr = Self::assign(tokens, pos);
-                expect(&tokens[*pos], TokenType::Semicolon, pos);
+                p.bump();
+                let expr = Self::expr(p);
+                p.expect(TokenType::Semicolon);
                 Self::new(NodeType::Return(Box::new(expr)))
             }
             _ => {
-                let expr = Self::assign(tokens, pos);
+                let expr = Self::expr(p);
                 let node = Self::new(NodeType::ExprStmt(Box::new(expr)));
-                expect(&tokens[*pos], TokenType::Semicolon, pos);
+                p.expect(TokenType::Semicolon);
                 node
             }
         }
     }
 
-    fn compound_stmt(tokens: &Vec<Token>, pos: &mut usize) -> Self {
+    fn compound_stmt(p: &mut Parser) -> Self {
         let mut stmts = vec![];
 
-        loop {
-            if tokens.len() == *pos {
-                let node = Self::new(NodeType::CompStmt(stmts));
-                return node;
+        enter_scope();
+        while !p.eat(TokenType::RightBrace) {
+            if p.at_eof() {
+                p.expect(TokenType::RightBrace);
+            }
+            stmts.push(Self::stmt(p));
+        }
+        leave_scope();
+        Self::new(NodeType::CompStmt(stmts))
+    }
+
+    // A function definition, a global variable definition or a type
+    // declaration.
+    fn toplevel(p: &mut Parser) -> Self {
+        if p.eat(TokenType::Typedef) {
+            return Self::typedef(p);
+        }
+
+        let (ty, mut syntax) = Self::expect_decl_type(p);
+        if p.eat(TokenType::Semicolon) {
+            return Self::type_decl(ty, syntax);
+        }
+
+        let ty = Self::ptr_type(p, ty, &mut syntax);
+        let name = Self::ident(p);
+        if !p.eat(TokenType::LeftParen) {
+            return Self::vardef(p, ty, syntax, name);
+        }
+        add_symbol(&name, Symbol::Var);
+
+        enter_scope();
+        let mut params = vec![];
+        if !p.eat(TokenType::RightParen) {
+            params.push(Self::param(p));
+            while p.eat(TokenType::Comma) {
+                params.push(Self::param(p));
             }
-            stmts.push(Self::stmt(tokens, pos));
+            p.expect(TokenType::RightParen);
+        }
+
+        let start = p.peek();
+        p.expect(TokenType::LeftBrace);
+        *LABELS.lock().unwrap() = FuncLabels::default();
+        let body = Self::compound_stmt(p);
+        let body = p.spanned(start, body);
+        leave_scope();
+        Self::func(name, params, body, ty, syntax)
+    }
+
+    fn func(name: String, params: Vec<Self>, body: Self, ty: Type, syntax: DeclSyntax) -> Self {
+        let labels = LABELS.lock().unwrap();
+        if let Some(name) = labels.gotos.iter().find(|l| !labels.defined.contains(*l)) {
+            panic!("use of undeclared label '{}'", name);
+        }
+        let mut node = Self::with_type(NodeType::Func(name, params, Box::new(body)), ty);
+        node.syntax = Some(Box::new(syntax));
+        node
+    }
+
+    // Statements up to the end of input, as the body of `int main()`.
+    fn bare_main(p: &mut Parser) -> Self {
+        let start = p.peek();
+        *LABELS.lock().unwrap() = FuncLabels::default();
+        enter_scope();
+        let mut stmts = vec![];
+        while !p.at_eof() {
+            stmts.push(Self::stmt(p));
         }
+        leave_scope();
+        let body = p.spanned(start, Self::new(NodeType::CompStmt(stmts)));
+        let syntax = DeclSyntax::new(TypeSpec::Int);
+        Self::func("main".to_string(), vec![], body, Type::int(), syntax)
     }
 
-    pub fn parse(tokens: &Vec<Token>) -> Self {
-        let mut pos = 0;
-        Self::compound_stmt(tokens, &mut pos)
+    // A program is a list of top-level definitions. One without any
+    // function definition is the body of main, as programs were written
+    // before there were functions and globals.
+    pub fn parse(tokens: &[Token]) -> Vec<Self> {
+        let mut p = Parser::new(tokens);
+        let mut nodes = vec![];
+        STRUCTS.lock().unwrap().clear();
+        *SCOPES.lock().unwrap() = vec![Scope::default()];
+        SWITCHES.lock().unwrap().clear();
+
+        if !p.at_eof() && !has_func_def(tokens) {
+            let start = p.peek();
+            let node = Self::bare_main(&mut p);
+            return vec![p.spanned(start, node)];
+        }
+
+        while !p.at_eof() {
+            let start = p.peek();
+            let node = Self::toplevel(&mut p);
+            nodes.push(p.spanned(start, node));
+        }
+        nodes
     }
 }
@@ -0,0 +1,738 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::Path,
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+
+use crate::token::{new_source, scan, Span, Token, TokenType};
+
+lazy_static! {
+    static ref MACROS: Mutex<HashMap<String, Macro>> = Mutex::new(HashMap::new());
+    static ref INCLUDE_PATHS: Mutex<Vec<String>> = Mutex::new(vec![]);
+    static ref INCLUDE_DEPTH: Mutex<usize> = Mutex::new(0);
+    static ref SCRATCH: Mutex<&'static mut [u8]> = Mutex::new(&mut []);
+}
+
+// Guards against a header which includes itself without a guard.
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+const SCRATCH_SIZE: usize = 1 << 16;
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Option<Vec<String>>, // None for an object-like macro
+    body: Vec<Token>,
+}
+
+// A token together with the names of the macros it was expanded from,
+// which must not be expanded again while rescanning it.
+#[derive(Debug, Clone)]
+struct PToken {
+    tok: Token,
+    hideset: HashSet<String>,
+}
+
+impl PToken {
+    fn new(tok: Token) -> Self {
+        Self {
+            tok,
+            hideset: HashSet::new(),
+        }
+    }
+}
+
+// State of the file being preprocessed. `#line` changes the reported line
+// number and file name.
+struct File {
+    name: String,
+    dir: String,
+    line_delta: i64,
+}
+
+// An #if group. Once one branch has been taken, the rest are skipped.
+struct Cond {
+    taken: bool,
+    in_else: bool,
+}
+
+pub fn add_include_path(dir: String) {
+    INCLUDE_PATHS.lock().unwrap().push(dir);
+}
+
+fn is_ident(t: &Token) -> bool {
+    t.text()
+        .starts_with(|c: char| c.is_alphabetic() || c == '_')
+}
+
+// Keeps the text of a token the preprocessor made up. Tokens refer to
+// their text, so it must live as long as they do; texts are packed into
+// one buffer, and a new one is only leaked when it fills up.
+fn scratch(text: &str) -> &'static str {
+    let mut buf = SCRATCH.lock().unwrap();
+    if buf.len() < text.len() {
+        *buf = Box::leak(vec![0; text.len().max(SCRATCH_SIZE)].into_boxed_slice());
+    }
+    let (head, tail) = std::mem::take(&mut *buf).split_at_mut(text.len());
+    head.copy_from_slice(text.as_bytes());
+    *buf = tail;
+    std::str::from_utf8(head).unwrap()
+}
+
+fn new_token(ty: TokenType, text: String, from: &Token) -> Token {
+    let src = scratch(&text);
+    Token {
+        ty,
+        src,
+        span: Span {
+            start: 0,
+            end: src.len(),
+        },
+        ..from.clone()
+    }
+}
+
+fn new_num(val: i64, from: &Token) -> Token {
+    new_token(TokenType::Num(val), val.to_string(), from)
+}
+
+fn new_str(bytes: Vec<u8>, from: &Token) -> Token {
+    let mut text = String::from("\"");
+    for b in bytes.iter() {
+        if *b == b'"' || *b == b'\\' {
+            text.push('\\');
+        }
+        text.push(*b as char);
+    }
+    text.push('"');
+    new_token(TokenType::Str(bytes), text, from)
+}
+
+// Removes the tokens up to the end of the current line.
+fn read_line(input: &mut VecDeque<PToken>) -> Vec<Token> {
+    let mut line = vec![];
+    while let Some(t) = input.front() {
+        if t.tok.bol {
+            break;
+        }
+        line.push(input.pop_front().unwrap().tok);
+    }
+    line
+}
+
+fn read_ident(input: &mut VecDeque<PToken>, directive: &str) -> String {
+    match input.front() {
+        Some(t) if !t.tok.bol && is_ident(&t.tok) => {
+            input.pop_front().unwrap().tok.text().to_string()
+        }
+        _ => panic!("macro name missing in #{}", directive),
+    }
+}
+
+fn define(input: &mut VecDeque<PToken>) {
+    let name = read_ident(input, "define");
+
+    // A function-like macro has a parenthesis right after its name.
+    let is_func = match input.front() {
+        Some(t) => !t.tok.bol && !t.tok.space && t.tok.ty == TokenType::LeftParen,
+        None => false,
+    };
+
+    let mut params = None;
+    if is_func {
+        input.pop_front();
+        let mut names = vec![];
+        let mut line = read_line(input).into_iter();
+        loop {
+            match line.next() {
+                Some(t) if t.ty == TokenType::RightParen && names.is_empty() => break,
+                Some(t) if is_ident(&t) => names.push(t.text().to_string()),
+                _ => panic!("invalid parameter list for macro {}", name),
+            }
+            match line.next() {
+                Some(t) if t.ty == TokenType::RightParen => break,
+                Some(t) if t.ty == TokenType::Comma => continue,
+                _ => panic!("expected ',' or ')' in parameter list of macro {}", name),
+            }
+        }
+        params = Some(names);
+
+        // The rest of the line was consumed with the parameters.
+        let body = line.collect();
+        MACROS.lock().unwrap().insert(name, Macro { params, body });
+        return;
+    }
+
+    let body = read_line(input);
+    MACROS.lock().unwrap().insert(name, Macro { params, body });
+}
+
+// Reads the arguments of a function-like macro invocation. The opening
+// parenthesis has already been consumed.
+fn read_args(input: &mut VecDeque<PToken>, name: &str) -> (Vec<Vec<PToken>>, PToken) {
+    let mut args = vec![vec![]];
+    let mut depth = 0;
+    loop {
+        let t = match input.pop_front() {
+            Some(t) => t,
+            None => panic!("unterminated argument list invoking macro {}", name),
+        };
+        match t.tok.ty {
+            TokenType::RightParen if depth == 0 => return (args, t),
+            TokenType::Comma if depth == 0 => {
+                args.push(vec![]);
+                continue;
+            }
+            TokenType::LeftParen => depth += 1,
+            TokenType::RightParen => depth -= 1,
+            _ => (),
+        }
+        args.last_mut().unwrap().push(t);
+    }
+}
+
+// `#x` is the spelling of the argument as a string literal.
+fn stringize(arg: &[PToken], from: &Token) -> Token {
+    let mut s = String::new();
+    for (i, t) in arg.iter().enumerate() {
+        if i > 0 && t.tok.space {
+            s.push(' ');
+        }
+        s.push_str(t.tok.text());
+    }
+    new_str(s.into_bytes(), from)
+}
+
+// `a ## b` is the token spelled by joining `a` and `b`.
+fn paste(lhs: &Token, rhs: &Token) -> Token {
+    let src = scratch(&format!("{}{}", lhs.text(), rhs.text()));
+    let mut tokens = scan(src);
+    if tokens.len() != 1 {
+        panic!(
+            "pasting \"{}\" and \"{}\" does not give a valid preprocessing token",
+            lhs.text(),
+            rhs.text()
+        );
+    }
+    let t = tokens.remove(0);
+    Token {
+        ty: t.ty,
+        src,
+        span: t.span,
+        ..lhs.clone()
+    }
+}
+
+// Replaces the parameters in a macro body with the arguments. Operands of
+// # and ## are used as written; other arguments are fully expanded first.
+fn substitute(m: &Macro, args: &[Vec<PToken>], file: &File) -> Vec<PToken> {
+    let params = m.params.clone().unwrap_or_default();
+    let arg = |t: &Token| params.iter().position(|p| *p == t.text()).map(|i| &args[i]);
+
+    let mut out: Vec<PToken> = vec![];
+    // Whether the last operand produced no tokens, like an empty argument
+    let mut last_empty = false;
+    let mut i = 0;
+    while i < m.body.len() {
+        let t = &m.body[i];
+        let next = m.body.get(i + 1);
+
+        if t.ty == TokenType::Hash && m.params.is_some() {
+            match next.and_then(arg) {
+                Some(a) => out.push(PToken::new(stringize(a, t))),
+                None => panic!("'#' is not followed by a macro parameter"),
+            }
+            last_empty = false;
+            i += 2;
+            continue;
+        }
+
+        if t.ty == TokenType::HashHash {
+            let rhs = match next {
+                Some(next) if i > 0 => match arg(next) {
+                    Some(a) => a.clone(),
+                    None => vec![PToken::new(next.clone())],
+                },
+                _ => panic!("'##' cannot appear at either end of a macro expansion"),
+            };
+            let mut rhs = rhs.into_iter();
+            if !last_empty {
+                if let Some(r) = rhs.next() {
+                    let l = out.pop().unwrap();
+                    out.push(PToken::new(paste(&l.tok, &r.tok)));
+                }
+            }
+            let before = out.len();
+            out.extend(rhs);
+            last_empty = last_empty && out.len() == before;
+            i += 2;
+            continue;
+        }
+
+        match arg(t) {
+            Some(a) => {
+                let pasted = next.map(|n| n.ty == TokenType::HashHash) == Some(true);
+                let mut a = if pasted {
+                    a.clone()
+                } else {
+                    expand_all(a.clone(), file)
+                };
+                if let Some(first) = a.first_mut() {
+                    first.tok.space = t.space;
+                }
+                last_empty = a.is_empty();
+                out.extend(a);
+            }
+            None => {
+                out.push(PToken::new(t.clone()));
+                last_empty = false;
+            }
+        }
+        i += 1;
+    }
+    out
+}
+
+// Expands the macro named by `t`, if any, by pushing its replacement back
+// onto the input to be rescanned. Returns false if `t` is not a macro.
+fn expand(t: &PToken, input: &mut VecDeque<PToken>, file: &File) -> bool {
+    let name = t.tok.text();
+    if !is_ident(&t.tok) || t.hideset.contains(name) {
+        return false;
+    }
+
+    match name {
+        "__LINE__" => {
+            let line = t.tok.line as i64 + file.line_delta;
+            input.push_front(PToken::new(new_num(line, &t.tok)));
+            return true;
+        }
+        "__FILE__" => {
+            let tok = new_str(file.name.clone().into_bytes(), &t.tok);
+            input.push_front(PToken::new(tok));
+            return true;
+        }
+        _ => (),
+    }
+
+    let m = match MACROS.lock().unwrap().get(name) {
+        Some(m) => m.clone(),
+        None => return false,
+    };
+
+    let mut hideset = t.hideset.clone();
+    let body = match m.params {
+        None => substitute(&m, &[], file),
+        Some(ref params) => {
+            // A function-like macro name without arguments is left alone.
+            match input.front() {
+                Some(next) if next.tok.ty == TokenType::LeftParen => input.pop_front(),
+                _ => return false,
+            };
+            let (args, rparen) = read_args(input, name);
+            hideset.retain(|n| rparen.hideset.contains(n));
+
+            let no_args = params.is_empty() && args.len() == 1 && args[0].is_empty();
+            if args.len() != params.len() && !no_args {
+                panic!(
+                    "macro {} requires {} arguments, but {} given",
+                    name,
+                    params.len(),
+                    args.len()
+                );
+            }
+            substitute(&m, &args, file)
+        }
+    };
+
+    hideset.insert(name.to_string());
+    for (i, mut pt) in body.into_iter().enumerate().rev() {
+        pt.hideset.extend(hideset.iter().cloned());
+        pt.tok.line = t.tok.line;
+        pt.tok.bol = false;
+        if i == 0 {
+            pt.tok.space = t.tok.space;
+        }
+        input.push_front(pt);
+    }
+    true
+}
+
+// Expands all macros in a list of tokens without directives.
+fn expand_all(tokens: Vec<PToken>, file: &File) -> Vec<PToken> {
+    let mut input: VecDeque<PToken> = tokens.into();
+    let mut out = vec![];
+    while let Some(t) = input.pop_front() {
+        if !expand(&t, &mut input, file) {
+            out.push(t);
+        }
+    }
+    out
+}
+
+// Skips a group excluded by conditional compilation, up to the #elif,
+// #else or #endif which ends it. Nested groups are skipped as a whole.
+fn skip_cond(input: &mut VecDeque<PToken>) {
+    let mut depth = 0;
+    while let Some(t) = input.pop_front() {
+        if !(t.tok.bol && t.tok.ty == TokenType::Hash) {
+            continue;
+        }
+        let directive = match input.front() {
+            Some(d) if !d.tok.bol => d.tok.text(),
+            _ => continue,
+        };
+        match directive {
+            "if" | "ifdef" | "ifndef" => depth += 1,
+            "elif" | "else" if depth == 0 => {
+                input.push_front(t);
+                return;
+            }
+            "endif" if depth == 0 => {
+                input.push_front(t);
+                return;
+            }
+            "endif" => depth -= 1,
+            _ => (),
+        }
+    }
+}
+
+// Evaluates the controlling expression of #if or #elif.
+fn eval_cond(line: Vec<Token>, file: &File) -> bool {
+    // Replace `defined X` and `defined(X)` before expanding macros.
+    let mut tokens = vec![];
+    let mut iter = line.into_iter();
+    while let Some(t) = iter.next() {
+        if t.text() != "defined" {
+            tokens.push(PToken::new(t));
+            continue;
+        }
+        let mut name = iter.next();
+        let paren = matches!(name, Some(ref n) if n.ty == TokenType::LeftParen);
+        if paren {
+            name = iter.next();
+        }
+        let name = match name {
+            Some(n) if is_ident(&n) => n,
+            _ => panic!("macro name missing after 'defined'"),
+        };
+        if paren && !matches!(iter.next(), Some(ref r) if r.ty == TokenType::RightParen) {
+            panic!("missing ')' after 'defined'");
+        }
+        let defined = MACROS.lock().unwrap().contains_key(name.text());
+        tokens.push(PToken::new(new_num(defined as i64, &t)));
+    }
+
+    // Identifiers left after expansion evaluate to 0.
+    let tokens: Vec<Token> = expand_all(tokens, file)
+        .into_iter()
+        .map(|t| match t.tok.ty {
+            TokenType::Num(_) => t.tok,
+            _ if is_ident(&t.tok) => new_num(0, &t.tok),
+            _ => t.tok,
+        })
+        .collect();
+
+    if tokens.is_empty() {
+        panic!("#if with no expression");
+    }
+    let mut pos = 0;
+    let val = cond_expr(&tokens, &mut pos);
+    if pos != tokens.len() {
+        panic!("missing binary operator before {}", tokens[pos].text());
+    }
+    val != 0
+}
+
+fn cond_expr(tokens: &[Token], pos: &mut usize) -> i64 {
+    let cond = binary_expr(tokens, pos, 0);
+    if tokens.get(*pos).map(|t| &t.ty) != Some(&TokenType::Question) {
+        return cond;
+    }
+    *pos += 1;
+    let then = cond_expr(tokens, pos);
+    match tokens.get(*pos) {
+        Some(t) if t.ty == TokenType::Colon => *pos += 1,
+        _ => panic!("expected ':' in preprocessor expression"),
+    }
+    let els = cond_expr(tokens, pos);
+    if cond != 0 {
+        then
+    } else {
+        els
+    }
+}
+
+// Binary operators of #if expressions, from the loosest binding.
+const BINARY_OPS: [&[TokenType]; 10] = [
+    &[TokenType::LogOr],
+    &[TokenType::LogAnd],
+    &[TokenType::Or],
+    &[TokenType::Xor],
+    &[TokenType::And],
+    &[TokenType::EqEq, TokenType::Ne],
+    &[TokenType::Lt, TokenType::Gt, TokenType::Le, TokenType::Ge],
+    &[TokenType::Shl, TokenType::Shr],
+    &[TokenType::Plus, TokenType::Minus],
+    &[TokenType::Mul, TokenType::Div, TokenType::Mod],
+];
+
+fn binary_expr(tokens: &[Token], pos: &mut usize, level: usize) -> i64 {
+    if level == BINARY_OPS.len() {
+        return unary_expr(tokens, pos);
+    }
+
+    let mut lhs = binary_expr(tokens, pos, level + 1);
+    while let Some(t) = tokens.get(*pos) {
+        if !BINARY_OPS[level].contains(&t.ty) {
+            break;
+        }
+        *pos += 1;
+        let rhs = binary_expr(tokens, pos, level + 1);
+        lhs = match t.ty {
+            TokenType::LogOr => (lhs != 0 || rhs != 0) as i64,
+            TokenType::LogAnd => (lhs != 0 && rhs != 0) as i64,
+            TokenType::Or => lhs | rhs,
+            TokenType::Xor => lhs ^ rhs,
+            TokenType::And => lhs & rhs,
+            TokenType::EqEq => (lhs == rhs) as i64,
+            TokenType::Ne => (lhs != rhs) as i64,
+            TokenType::Lt => (lhs < rhs) as i64,
+            TokenType::Gt => (lhs > rhs) as i64,
+            TokenType::Le => (lhs <= rhs) as i64,
+            TokenType::Ge => (lhs >= rhs) as i64,
+            TokenType::Shl => lhs.wrapping_shl(rhs as u32),
+            TokenType::Shr => lhs.wrapping_shr(rhs as u32),
+            TokenType::Plus => lhs.wrapping_add(rhs),
+            TokenType::Minus => lhs.wrapping_sub(rhs),
+            TokenType::Mul => lhs.wrapping_mul(rhs),
+            TokenType::Div | TokenType::Mod if rhs == 0 => {
+                panic!("division by zero in preprocessor expression")
+            }
+            TokenType::Div => lhs.wrapping_div(rhs),
+            TokenType::Mod => lhs.wrapping_rem(rhs),
+            _ => unreachable!(),
+        };
+    }
+    lhs
+}
+
+fn unary_expr(tokens: &[Token], pos: &mut usize) -> i64 {
+    let t = match tokens.get(*pos) {
+        Some(t) => t,
+        None => panic!("unexpected end of preprocessor expression"),
+    };
+    *pos += 1;
+    match t.ty {
+        TokenType::Num(val) => val,
+        TokenType::Plus => unary_expr(tokens, pos),
+        TokenType::Minus => unary_expr(tokens, pos).wrapping_neg(),
+        TokenType::Not => (unary_expr(tokens, pos) == 0) as i64,
+        TokenType::Tilde => !unary_expr(tokens, pos),
+        TokenType::LeftParen => {
+            let val = cond_expr(tokens, pos);
+            match tokens.get(*pos) {
+                Some(t) if t.ty == TokenType::RightParen => *pos += 1,
+                _ => panic!("expected ')' in preprocessor expression"),
+            }
+            val
+        }
+        _ => panic!("invalid token in preprocessor expression: {}", t.text()),
+    }
+}
+
+// Finds the file named by #include. A quoted name is looked up next to the
+// including file first, then in the include paths.
+fn find_include(name: &str, quoted: bool, file: &File) -> String {
+    let mut dirs = vec![];
+    if quoted {
+        dirs.push(file.dir.clone());
+    }
+    dirs.extend(INCLUDE_PATHS.lock().unwrap().iter().cloned());
+
+    for dir in dirs {
+        let path = Path::new(&dir).join(name);
+        if path.is_file() {
+            return path.to_string_lossy().into_owned();
+        }
+    }
+    panic!("'{}' file not found", name);
+}
+
+fn include(line: Vec<Token>, file: &File) -> Vec<PToken> {
+    let line: Vec<Token> = match line.first() {
+        Some(t) if matches!(t.ty, TokenType::Str(_) | TokenType::Lt) => line,
+        // #include MACRO
+        _ => {
+            let line = line.into_iter().map(PToken::new).collect();
+            expand_all(line, file).into_iter().map(|t| t.tok).collect()
+        }
+    };
+
+    let (name, quoted) = match line.first().map(|t| &t.ty) {
+        Some(TokenType::Str(bytes)) if line.len() == 1 => {
+            (String::from_utf8_lossy(bytes).into_owned(), true)
+        }
+        Some(TokenType::Lt) if line.last().unwrap().ty == TokenType::Gt && line.len() > 2 => {
+            let name: String = line[1..line.len() - 1].iter().map(|t| t.text()).collect();
+            (name, false)
+        }
+        _ => panic!("#include expects \"FILENAME\" or <FILENAME>"),
+    };
+
+    let path = find_include(&name, quoted, file);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => panic!("cannot read {}: {}", path, e),
+    };
+
+    if *INCLUDE_DEPTH.lock().unwrap() >= MAX_INCLUDE_DEPTH {
+        panic!("#include nested too deeply: {}", path);
+    }
+    *INCLUDE_DEPTH.lock().unwrap() += 1;
+    let tokens = preprocess_file(scan(new_source(contents)), &path);
+    *INCLUDE_DEPTH.lock().unwrap() -= 1;
+    tokens
+}
+
+// Runs one directive. The `#` has been consumed.
+fn directive(
+    input: &mut VecDeque<PToken>,
+    out: &mut Vec<PToken>,
+    conds: &mut Vec<Cond>,
+    file: &mut File,
+    hash: &Token,
+) {
+    // The null directive
+    let name = match input.front() {
+        Some(t) if !t.tok.bol => input.pop_front().unwrap().tok,
+        _ => return,
+    };
+
+    match name.text() {
+        "define" => define(input),
+        "undef" => {
+            let macro_name = read_ident(input, "undef");
+            read_line(input);
+            MACROS.lock().unwrap().remove(&macro_name);
+        }
+        "include" => {
+            let line = read_line(input);
+            out.extend(include(line, file));
+        }
+        "if" | "ifdef" | "ifndef" => {
+            let taken = match name.text() {
+                "if" => eval_cond(read_line(input), file),
+                directive => {
+                    let macro_name = read_ident(input, directive);
+                    read_line(input);
+                    let defined = MACROS.lock().unwrap().contains_key(&macro_name);
+                    defined == (directive == "ifdef")
+                }
+            };
+            conds.push(Cond {
+                taken,
+                in_else: false,
+            });
+            if !taken {
+                skip_cond(input);
+            }
+        }
+        "elif" => {
+            let line = read_line(input);
+            let cond = match conds.last_mut() {
+                Some(cond) if !cond.in_else => cond,
+                Some(_) => panic!("#elif after #else"),
+                None => panic!("#elif without #if"),
+            };
+            if cond.taken || !eval_cond(line, file) {
+                skip_cond(input);
+            } else {
+                cond.taken = true;
+            }
+        }
+        "else" => {
+            read_line(input);
+            let cond = match conds.last_mut() {
+                Some(cond) if !cond.in_else => cond,
+                Some(_) => panic!("#else after #else"),
+                None => panic!("#else without #if"),
+            };
+            cond.in_else = true;
+            if cond.taken {
+                skip_cond(input);
+            } else {
+                cond.taken = true;
+            }
+        }
+        "endif" => {
+            read_line(input);
+            if conds.pop().is_none() {
+                panic!("#endif without #if");
+            }
+        }
+        "error" => {
+            let msg: Vec<&str> = read_line(input).iter().map(|t| t.text()).collect();
+            panic!("{}:{}: #error {}", file.name, hash.line, msg.join(" "));
+        }
+        "line" => {
+            let line = read_line(input).into_iter().map(PToken::new).collect();
+            let line: Vec<Token> = expand_all(line, file).into_iter().map(|t| t.tok).collect();
+
+            // The line after the directive gets the given number.
+            match line.first().map(|t| &t.ty) {
+                Some(TokenType::Num(n)) => file.line_delta = n - hash.line as i64 - 1,
+                _ => panic!("#line directive requires a positive integer argument"),
+            }
+            match line.get(1).map(|t| &t.ty) {
+                Some(TokenType::Str(bytes)) => file.name = String::from_utf8_lossy(bytes).into(),
+                None => (),
+                _ => panic!("invalid filename for #line directive"),
+            }
+        }
+        d => panic!("invalid preprocessing directive: #{}", d),
+    }
+}
+
+fn preprocess_file(tokens: Vec<Token>, path: &str) -> Vec<PToken> {
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    };
+    let mut file = File {
+        name: path.to_string(),
+        dir,
+        line_delta: 0,
+    };
+
+    let mut input: VecDeque<PToken> = tokens.into_iter().map(PToken::new).collect();
+    let mut out = vec![];
+    let mut conds = vec![];
+
+    while let Some(t) = input.pop_front() {
+        if t.tok.bol && t.tok.ty == TokenType::Hash {
+            directive(&mut input, &mut out, &mut conds, &mut file, &t.tok);
+            continue;
+        }
+        if !expand(&t, &mut input, &file) {
+            out.push(t);
+        }
+    }
+
+    if !conds.is_empty() {
+        panic!("unterminated conditional directive in {}", file.name);
+    }
+    out
+}
+
+// Runs the preprocessor over the tokens of the file at `path`.
+pub fn preprocess(tokens: Vec<Token>, path: &str) -> Vec<Token> {
+    MACROS.lock().unwrap().clear();
+    preprocess_file(tokens, path)
+        .into_iter()
+        .map(|t| t.tok)
+        .collect()
+}
@@ -2,7 +2,7 @@ use std::sync::Mutex;
 
 use lazy_static::lazy_static;
 
-use crate::ir::{get_irinfo, IROp, IRType, IR};
+use crate::ir::{VReg, IR};
 use crate::REGS_N;
 
 lazy_static! {
@@ -26,10 +26,10 @@ fn reg_map_set(i: usize, val: usize) {
     REG_MAP.lock().unwrap()[i] = Some(val);
 }
 
-fn alloc(ir_reg: usize) -> usize {
-    if let Some(r) = reg_map_get(ir_reg) {
+fn alloc(ir_reg: VReg) -> VReg {
+    if let Some(r) = reg_map_get(ir_reg.0) {
         assert!(used_get(r));
-        return r;
+        return VReg(r);
     }
 
     for i in 0..REGS_N {
@@ -37,40 +37,73 @@ fn alloc(ir_reg: usize) -> usize {
             continue;
         }
         used_set(i, true);
-        reg_map_set(ir_reg, i);
-        return i;
+        reg_map_set(ir_reg.0, i);
+        return VReg(i);
     }
     panic!("register exhauseted");
 }
 
-fn kill(r: usize) {
-    assert!(used_get(r));
-    used_set(r, false);
+fn kill(r: VReg) {
+    assert!(used_get(r.0));
+    used_set(r.0, false);
 }
 
-pub fn alloc_regs(irv: &mut Vec<IR>) {
-    use IRType::*;
-    let irv_len = irv.len();
-
-    *REG_MAP.lock().unwrap() = vec![None; irv_len];
-
-    for i in 0..irv_len {
-        let mut ir = irv[i].clone();
-        let info = get_irinfo(&ir);
+// Lowering to two-address form copies the left operand into the
+// destination before the operation, which would clobber a right operand
+// living in the same register. So an instruction whose destination is its
+// right operand but not its left reads the right one from a copy instead.
+// Returns the number of virtual registers.
+fn untie(irv: &mut Vec<IR>) -> usize {
+    let mut nregs = 0;
+    for ir in irv.iter_mut() {
+        ir.map_regs(&mut |r| {
+            nregs = nregs.max(r.0 + 1);
+            r
+        });
+    }
 
-        match info.ty {
-            Reg | RegImm | RegLabel => ir.lhs = Some(alloc(ir.lhs.unwrap())),
-            RegReg => {
-                ir.lhs = Some(alloc(ir.lhs.unwrap()));
-                ir.rhs = Some(alloc(ir.rhs.unwrap()));
+    let mut out = Vec::with_capacity(irv.len());
+    for mut ir in irv.drain(..) {
+        if let Some((dst, lhs, Some(rhs))) = ir.arith_operands_mut() {
+            if *rhs == *dst && *lhs != *dst {
+                let tmp = VReg(nregs);
+                nregs += 1;
+                out.push(IR::Mov {
+                    lhs: tmp,
+                    rhs: *rhs,
+                });
+                *rhs = tmp;
+                out.push(ir);
+                out.push(IR::Kill { lhs: tmp });
+                continue;
             }
-            _ => (),
         }
+        out.push(ir);
+    }
+    *irv = out;
+    nregs
+}
+
+pub fn alloc_regs(irv: &mut Vec<IR>) {
+    let nregs = untie(irv);
+    *REG_MAP.lock().unwrap() = vec![None; nregs];
 
-        if ir.op == IROp::Kill {
-            kill(ir.lhs.unwrap());
-            ir.op = IROp::Nop;
+    for ir in irv.iter_mut() {
+        match ir {
+            IR::Call { lhs, args, .. } => {
+                // A call consumes its arguments, so their registers are
+                // free to hold the return value.
+                for arg in args.iter_mut() {
+                    *arg = alloc(*arg);
+                    kill(*arg);
+                }
+                *lhs = alloc(*lhs);
+            }
+            IR::Kill { lhs } => {
+                kill(alloc(*lhs));
+                *ir = IR::Nop;
+            }
+            _ => ir.map_regs(&mut alloc),
         }
-        irv[i] = ir;
     }
 }
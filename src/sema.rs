@@ -0,0 +1,693 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use lazy_static::lazy_static;
+
+use crate::parse::{eval, roundup, struct_def, Ctype, Member, Node, NodeType, Type};
+use crate::token::TokenType;
+
+lazy_static! {
+    static ref SCOPES: Mutex<Vec<HashMap<String, Var>>> = Mutex::new(vec![]);
+    static ref FUNCS: Mutex<HashMap<String, Type>> = Mutex::new(HashMap::new());
+    static ref RETURN_TYPE: Mutex<Type> = Mutex::new(Type::int());
+    static ref STACKSIZE: Mutex<usize> = Mutex::new(0);
+    static ref GLOBALS: Mutex<Vec<Global>> = Mutex::new(vec![]);
+    static ref STR_LABEL: Mutex<usize> = Mutex::new(0);
+}
+
+// Arguments are passed in registers, so a call takes at most six of them.
+const MAX_ARGS: usize = 6;
+
+#[derive(Debug, Clone)]
+enum Var {
+    Local(Type, usize), // type, offset from the frame base
+    Global(Type),       // type; the label is the variable name
+}
+
+// A piece of the initial contents of a global.
+#[derive(Debug, Clone)]
+pub enum Data {
+    Bytes(Vec<u8>), // Literal bytes
+    Label(String),  // Address of another global
+}
+
+// A statically allocated object, emitted into the data section. Objects
+// without initial contents go to .bss. Only the layout of its type is kept,
+// so that globals can also be read from textual IR.
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub name: String,
+    pub size: usize,
+    pub align: usize,
+    pub data: Option<Vec<Data>>,
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Node>,
+    pub body: Node,
+    pub stacksize: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub funcs: Vec<Function>,
+    pub globals: Vec<Global>,
+}
+
+fn enter_scope() {
+    SCOPES.lock().unwrap().push(HashMap::new());
+}
+
+fn leave_scope() {
+    SCOPES.lock().unwrap().pop();
+}
+
+fn find_var(name: &str) -> Option<Var> {
+    let scopes = SCOPES.lock().unwrap();
+    scopes
+        .iter()
+        .rev()
+        .find_map(|scope| scope.get(name).cloned())
+}
+
+fn add_var(name: String, var: Var) {
+    let mut scopes = SCOPES.lock().unwrap();
+    let scope = scopes.last_mut().unwrap();
+    if scope.contains_key(&name) {
+        panic!("redefinition of {}", name);
+    }
+    scope.insert(name, var);
+}
+
+fn add_local(name: String, ty: Type) -> Node {
+    let offset = roundup(*STACKSIZE.lock().unwrap(), ty.align());
+    *STACKSIZE.lock().unwrap() = offset + ty.size();
+    add_var(name, Var::Local(ty.clone(), offset));
+    Node::with_type(NodeType::Lvar(offset), ty)
+}
+
+// Wraps a node with a conversion to `ty` if its type differs.
+fn convert(node: Node, ty: &Type) -> Node {
+    if node.ctype == *ty {
+        return node;
+    }
+    if node.ctype.is_struct() || ty.is_struct() {
+        panic!("incompatible types: {:?} and {:?}", node.ctype, ty);
+    }
+    Node::with_type(NodeType::Cast(Box::new(node)), ty.clone())
+}
+
+// Usual arithmetic conversion. Every operand is widened to at least int.
+fn arith_type(lhs: &Type, rhs: &Type) -> Type {
+    if lhs.is_ptr() || rhs.is_ptr() || lhs.is_struct() || rhs.is_struct() {
+        panic!("invalid operands to binary expression");
+    }
+    if lhs.size() == 8 || rhs.size() == 8 {
+        return Type::long();
+    }
+    Type::int()
+}
+
+// The common type of the arms of `?:`. Arithmetic arms are converted as
+// for a binary operator; otherwise the arms must agree, except that a
+// pointer arm wins over an integer one.
+fn cond_type(then: &Type, els: &Type) -> Type {
+    if then == els {
+        return then.clone();
+    }
+    if then.is_ptr() {
+        return then.clone();
+    }
+    if els.is_ptr() {
+        return els.clone();
+    }
+    if then.is_struct() || els.is_struct() {
+        panic!("incompatible operand types: {:?} and {:?}", then, els);
+    }
+    arith_type(then, els)
+}
+
+// `line` is where the operand was written, since walking it may have
+// replaced it with a node that wasn't parsed, like the address of an array.
+fn check_lval(node: &Node, line: Option<usize>) {
+    match node.ty {
+        NodeType::Lvar(_) | NodeType::Gvar(_) | NodeType::Deref(_) | NodeType::Dot(..) => (),
+        _ => match line {
+            Some(line) => panic!("expression is not assignable at line {}", line),
+            None => panic!("expression is not assignable"),
+        },
+    }
+}
+
+fn binop(op: TokenType, lhs: Node, rhs: Node, ty: Type) -> Node {
+    Node::with_type(NodeType::BinOp(op, Box::new(lhs), Box::new(rhs)), ty)
+}
+
+// Multiplies an integer operand of pointer arithmetic by the pointee size.
+fn scale(node: Node, size: usize) -> Node {
+    let ty = arith_type(&node.ctype, &Type::int());
+    binop(
+        TokenType::Mul,
+        node,
+        Node::new(NodeType::Num(size as i64)),
+        ty,
+    )
+}
+
+// `+` and `-` with a pointer operand are scaled by the size of the pointee,
+// and the difference of two pointers is the number of elements between them.
+fn ptr_arith(op: TokenType, mut lhs: Node, mut rhs: Node) -> Node {
+    if op == TokenType::Plus && rhs.ctype.is_ptr() {
+        std::mem::swap(&mut lhs, &mut rhs);
+    }
+
+    if rhs.ctype.is_ptr() {
+        let size = match lhs.ctype.pointee() {
+            Some(base) if op == TokenType::Minus => base.size(),
+            _ => panic!("invalid operands to binary expression"),
+        };
+        let diff = binop(op, lhs, rhs, Type::long());
+        let size = Node::new(NodeType::Num(size as i64));
+        return binop(TokenType::Div, diff, size, Type::long());
+    }
+
+    let ty = lhs.ctype.clone();
+    let size = ty.pointee().unwrap().size();
+    binop(op, lhs, scale(rhs, size), ty)
+}
+
+// `a op= b` keeps the type of `a`. A pointer may only be moved by an
+// integer, which is scaled like in pointer arithmetic.
+fn compound_assign(op: TokenType, lhs: Node, rhs: Node, line: Option<usize>) -> Node {
+    check_lval(&lhs, line);
+    let ty = lhs.ctype.clone();
+    if let Some(base) = ty.pointee() {
+        if op != TokenType::AddEq && op != TokenType::SubEq {
+            panic!("invalid operands to binary expression");
+        }
+        let rhs = scale(rhs, base.size());
+        return binop(op, lhs, rhs, ty);
+    }
+    arith_type(&lhs.ctype, &rhs.ctype);
+    binop(op, lhs, rhs, ty)
+}
+
+// x++ is (x+=1)-1 converted back to the type of x, so that x is evaluated
+// only once. x-- is (x-=1)+1.
+fn post_incdec(assign: TokenType, undo: TokenType, expr: Node) -> Node {
+    let line = expr.line;
+    let expr = walk(expr);
+    let ty = expr.ctype.clone();
+    let one = Node::new(NodeType::Num(1));
+    let node = compound_assign(assign, expr, one.clone(), line);
+    let node = if ty.is_ptr() {
+        ptr_arith(undo, node, one)
+    } else {
+        let arith_ty = arith_type(&ty, &Type::int());
+        binop(undo, node, one, arith_ty)
+    };
+    convert(node, &ty)
+}
+
+// Arrays are converted to a pointer to their first element, except as the
+// operand of & and sizeof.
+fn decay(node: Node) -> Node {
+    match node.ctype.ty {
+        Ctype::Ary(ref base, _) => {
+            let ty = Type::ptr_to((**base).clone());
+            Node::with_type(NodeType::Addr(Box::new(node)), ty)
+        }
+        _ => node,
+    }
+}
+
+fn walk(node: Node) -> Node {
+    decay(walk_nodecay(node))
+}
+
+// Elements of an initializer for an array of `len` elements. A string
+// literal may initialize a char array.
+fn init_elems(name: &str, base: &Type, len: usize, init: Option<Node>) -> Vec<Node> {
+    let elems = match init.map(|init| init.ty) {
+        None => vec![],
+        Some(NodeType::InitList(elems)) => elems,
+        Some(NodeType::Str(mut data)) if base.ty == Ctype::Char => {
+            if data.len() < len {
+                data.push(0);
+            }
+            data.into_iter()
+                .map(|b| Node::new(NodeType::Num(b as i8 as i64)))
+                .collect()
+        }
+        Some(_) => panic!("invalid initializer for array {}", name),
+    };
+
+    if elems.len() > len {
+        panic!("excess elements in initializer of {}", name);
+    }
+    elems
+}
+
+// Members of a struct which an initializer list assigns, in order.
+fn init_members(name: &str, ty: &Type, mut members: Vec<Member>) -> Vec<Member> {
+    if let Ctype::Struct(id) = ty.ty {
+        if struct_def(id).is_union {
+            members.truncate(1);
+        }
+    }
+    if members.is_empty() {
+        panic!("empty struct cannot be initialized: {}", name);
+    }
+    members
+}
+
+// Lowers the initializer of a local to a list of assignments. Elements
+// without an initializer are set to zero.
+fn local_init(name: &str, var: Node, init: Option<Node>) -> Vec<Node> {
+    if let Ctype::Ary(ref base, len) = var.ctype.ty {
+        let base = (**base).clone();
+        let elems = init_elems(name, &base, len, init);
+        let mut elems = elems.into_iter();
+        let addr_ty = Type::ptr_to(base.clone());
+        let addr = Node::with_type(NodeType::Addr(Box::new(var)), addr_ty.clone());
+
+        let mut stmts = vec![];
+        for i in 0..len {
+            let off = Node::with_type(NodeType::Num((i * base.size()) as i64), Type::long());
+            let elem = binop(TokenType::Plus, addr.clone(), off, addr_ty.clone());
+            let elem = Node::with_type(NodeType::Deref(Box::new(elem)), base.clone());
+            stmts.extend(local_init(name, elem, elems.next()));
+        }
+        return stmts;
+    }
+
+    if let Some(members) = var.ctype.members() {
+        if let Some(Node {
+            ty: NodeType::InitList(elems),
+            ..
+        }) = init
+        {
+            let members = init_members(name, &var.ctype, members);
+            if elems.len() > members.len() {
+                panic!("excess elements in initializer of {}", name);
+            }
+
+            let mut elems = elems.into_iter();
+            let mut stmts = vec![];
+            for m in members {
+                let elem = Node::with_type(NodeType::Dot(Box::new(var.clone()), m.name), m.ty);
+                stmts.extend(local_init(name, elem, elems.next()));
+            }
+            return stmts;
+        }
+    }
+
+    let init = match init {
+        Some(Node {
+            ty: NodeType::InitList(_),
+            ..
+        }) => panic!("invalid initializer for {}", name),
+        Some(init) => init,
+        None if var.ctype.is_struct() => {
+            return local_init(name, var, Some(Node::new(NodeType::InitList(vec![]))))
+        }
+        None => Node::new(NodeType::Num(0)),
+    };
+    let ty = var.ctype.clone();
+    let rhs = convert(walk(init), &ty);
+    let assign = binop(TokenType::Equal, var, rhs, ty);
+    vec![Node::new(NodeType::ExprStmt(Box::new(assign)))]
+}
+
+// The label of a global whose address is the value of `node`, if any.
+fn eval_label(node: &Node) -> Option<String> {
+    match node.ty {
+        NodeType::Cast(ref expr) => eval_label(expr),
+        NodeType::Addr(ref expr) => match expr.ty {
+            NodeType::Gvar(ref name) => Some(name.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Computes the initial contents of a global.
+fn global_init(name: &str, ty: &Type, init: Option<Node>) -> Vec<Data> {
+    if let Ctype::Ary(ref base, len) = ty.ty {
+        let elems = init_elems(name, base, len, init);
+        let mut elems = elems.into_iter();
+        return (0..len)
+            .flat_map(|_| global_init(name, base, elems.next()))
+            .collect();
+    }
+
+    if let Some(members) = ty.members() {
+        let elems = match init.map(|init| init.ty) {
+            None => vec![],
+            Some(NodeType::InitList(elems)) => elems,
+            Some(_) => panic!(
+                "initializer element is not a compile-time constant: {}",
+                name
+            ),
+        };
+        let members = init_members(name, ty, members);
+        if elems.len() > members.len() {
+            panic!("excess elements in initializer of {}", name);
+        }
+
+        // Padding between members and at the end is filled with zeros.
+        let mut elems = elems.into_iter();
+        let mut data = vec![];
+        let mut off = 0;
+        for m in members {
+            if m.offset > off {
+                data.push(Data::Bytes(vec![0; m.offset - off]));
+            }
+            data.extend(global_init(name, &m.ty, elems.next()));
+            off = m.offset + m.ty.size();
+        }
+        if ty.size() > off {
+            data.push(Data::Bytes(vec![0; ty.size() - off]));
+        }
+        return data;
+    }
+
+    let init = match init {
+        Some(Node {
+            ty: NodeType::InitList(_),
+            ..
+        }) => panic!("invalid initializer for {}", name),
+        Some(init) => convert(walk(init), ty),
+        None => return vec![Data::Bytes(vec![0; ty.size()])],
+    };
+
+    if let Some(label) = eval_label(&init) {
+        if ty.size() != 8 {
+            panic!(
+                "initializer element is not computable at load time: {}",
+                name
+            );
+        }
+        return vec![Data::Label(label)];
+    }
+    let val = eval(&init);
+    vec![Data::Bytes(val.to_le_bytes()[..ty.size()].to_vec())]
+}
+
+fn walk_nodecay(node: Node) -> Node {
+    match node.ty {
+        NodeType::Num(_) => node,
+        NodeType::EnumConst(_, val) => Node::new(NodeType::Num(val)),
+        NodeType::Str(mut data) => {
+            // A string literal is an anonymous, NUL-terminated char array.
+            data.push(0);
+            let ty = Type::array_of(Type::char(), data.len());
+            let name = format!(".L.str{}", *STR_LABEL.lock().unwrap());
+            *STR_LABEL.lock().unwrap() += 1;
+            GLOBALS.lock().unwrap().push(Global {
+                name: name.clone(),
+                size: ty.size(),
+                align: ty.align(),
+                data: Some(vec![Data::Bytes(data)]),
+                readonly: true,
+            });
+            Node::with_type(NodeType::Gvar(name), ty)
+        }
+        NodeType::Ident(name) => match find_var(&name) {
+            Some(Var::Local(ty, offset)) => Node::with_type(NodeType::Lvar(offset), ty),
+            Some(Var::Global(ty)) => Node::with_type(NodeType::Gvar(name), ty),
+            None => panic!("undefined variable: {}", name),
+        },
+        NodeType::Vardef(name, init) => {
+            // A definition is lowered to assignments of its initializer.
+            let var = add_local(name.clone(), node.ctype);
+            match init {
+                Some(init) => Node::new(NodeType::CompStmt(local_init(&name, var, Some(*init)))),
+                None => Node::new(NodeType::CompStmt(vec![])),
+            }
+        }
+        NodeType::BinOp(TokenType::Equal, lhs, rhs) => {
+            let line = lhs.line;
+            let lhs = walk(*lhs);
+            check_lval(&lhs, line);
+            let rhs = convert(walk(*rhs), &lhs.ctype);
+            let ty = lhs.ctype.clone();
+            binop(TokenType::Equal, lhs, rhs, ty)
+        }
+        NodeType::BinOp(op, lhs, rhs) if op.is_compound_assign() => {
+            let line = lhs.line;
+            compound_assign(op, walk(*lhs), walk(*rhs), line)
+        }
+        NodeType::BinOp(TokenType::Comma, lhs, rhs) => {
+            let lhs = walk(*lhs);
+            let rhs = walk(*rhs);
+            let ty = rhs.ctype.clone();
+            binop(TokenType::Comma, lhs, rhs, ty)
+        }
+        NodeType::BinOp(op, lhs, rhs) if op.is_comparison() => {
+            // Comparisons and logical operators yield int, and also accept
+            // pointers.
+            let lhs = walk(*lhs);
+            let rhs = walk(*rhs);
+            if lhs.ctype.is_struct() || rhs.ctype.is_struct() {
+                panic!("invalid operands to binary expression");
+            }
+            binop(op, lhs, rhs, Type::int())
+        }
+        NodeType::BinOp(op, lhs, rhs) => {
+            let lhs = walk(*lhs);
+            let rhs = walk(*rhs);
+            let is_additive = op == TokenType::Plus || op == TokenType::Minus;
+            if is_additive && (lhs.ctype.is_ptr() || rhs.ctype.is_ptr()) {
+                return ptr_arith(op, lhs, rhs);
+            }
+            let ty = arith_type(&lhs.ctype, &rhs.ctype);
+
+            // A shift has the type of its left operand.
+            let ty = match op {
+                TokenType::Shl | TokenType::Shr => arith_type(&lhs.ctype, &Type::int()),
+                _ => ty,
+            };
+            binop(op, lhs, rhs, ty)
+        }
+        NodeType::Ternary(cond, then, els) => {
+            let cond = walk(*cond);
+            let then = walk(*then);
+            let els = walk(*els);
+            let ty = cond_type(&then.ctype, &els.ctype);
+            Node::with_type(
+                NodeType::Ternary(
+                    Box::new(cond),
+                    Box::new(convert(then, &ty)),
+                    Box::new(convert(els, &ty)),
+                ),
+                ty,
+            )
+        }
+        NodeType::PostInc(expr) => post_incdec(TokenType::AddEq, TokenType::Minus, *expr),
+        NodeType::PostDec(expr) => post_incdec(TokenType::SubEq, TokenType::Plus, *expr),
+
+        // ++x and --x are x+=1 and x-=1.
+        NodeType::PreInc(expr) => {
+            let one = Box::new(Node::new(NodeType::Num(1)));
+            walk_nodecay(Node::new(NodeType::BinOp(TokenType::AddEq, expr, one)))
+        }
+        NodeType::PreDec(expr) => {
+            let one = Box::new(Node::new(NodeType::Num(1)));
+            walk_nodecay(Node::new(NodeType::BinOp(TokenType::SubEq, expr, one)))
+        }
+
+        // -x is 0-x, +x is 0+x, !x is x==0 and ~x is x^-1.
+        NodeType::Unary(op, expr) => {
+            let num = |val| Box::new(Node::new(NodeType::Num(val)));
+            let node = match op {
+                TokenType::Minus => NodeType::BinOp(TokenType::Minus, num(0), expr),
+                TokenType::Plus => NodeType::BinOp(TokenType::Plus, num(0), expr),
+                TokenType::Not => NodeType::BinOp(TokenType::EqEq, expr, num(0)),
+                TokenType::Tilde => NodeType::BinOp(TokenType::Xor, expr, num(-1)),
+                _ => unreachable!(),
+            };
+            walk_nodecay(Node::new(node))
+        }
+
+        // a[i] is a shorthand for *(a+i).
+        NodeType::Index(expr, index) => {
+            let addr = Node::new(NodeType::BinOp(TokenType::Plus, expr, index));
+            walk_nodecay(Node::new(NodeType::Deref(Box::new(addr))))
+        }
+        NodeType::Call(name, args) => {
+            if args.len() > MAX_ARGS {
+                panic!("too many arguments to {}: {}", name, args.len());
+            }
+            let args: Vec<Node> = args.into_iter().map(walk).collect();
+            if args.iter().any(|arg| arg.ctype.is_struct()) {
+                panic!("passing a struct by value is not supported: {}", name);
+            }
+
+            // Undeclared functions are assumed to return int.
+            let ty = match FUNCS.lock().unwrap().get(&name) {
+                Some(ty) => ty.clone(),
+                None => Type::int(),
+            };
+            Node::with_type(NodeType::Call(name, args), ty)
+        }
+        NodeType::Addr(expr) => {
+            let line = expr.line;
+            let expr = walk_nodecay(*expr);
+            check_lval(&expr, line);
+            let ty = Type::ptr_to(expr.ctype.clone());
+            Node::with_type(NodeType::Addr(Box::new(expr)), ty)
+        }
+        NodeType::Deref(expr) => {
+            let expr = walk(*expr);
+            let ty = match expr.ctype.pointee() {
+                Some(base) => base.clone(),
+                None => panic!("operand of unary * must be a pointer"),
+            };
+            Node::with_type(NodeType::Deref(Box::new(expr)), ty)
+        }
+        // p->x is a shorthand for (*p).x.
+        NodeType::Arrow(expr, name) => {
+            let expr = Node::new(NodeType::Deref(expr));
+            walk_nodecay(Node::new(NodeType::Dot(Box::new(expr), name)))
+        }
+        NodeType::Dot(expr, name) => {
+            let expr = walk_nodecay(*expr);
+            if !expr.ctype.is_struct() {
+                panic!("member reference base type is not a struct: {}", name);
+            }
+            let ty = match expr.ctype.member(&name) {
+                Some(m) => m.ty,
+                None => panic!("no member named {}", name),
+            };
+            Node::with_type(NodeType::Dot(Box::new(expr), name), ty)
+        }
+        NodeType::Sizeof(expr) => {
+            let expr = walk_nodecay(*expr);
+            Node::with_type(NodeType::Num(expr.ctype.size() as i64), Type::long())
+        }
+        NodeType::SizeofType(ty) => Node::with_type(NodeType::Num(ty.size() as i64), Type::long()),
+        NodeType::If(cond, then, els) => Node::new(NodeType::If(
+            Box::new(walk(*cond)),
+            Box::new(walk(*then)),
+            els.map(|els| Box::new(walk(*els))),
+        )),
+        NodeType::Switch(cond, body, cases, has_default) => {
+            let cond = walk(*cond);
+            if cond.ctype.is_ptr() || cond.ctype.is_struct() {
+                panic!("statement requires expression of integer type");
+            }
+            Node::new(NodeType::Switch(
+                Box::new(cond),
+                Box::new(walk(*body)),
+                cases,
+                has_default,
+            ))
+        }
+        NodeType::Case(_, val, body) => {
+            let expr = Box::new(Node::new(NodeType::Num(val)));
+            Node::new(NodeType::Case(expr, val, Box::new(walk(*body))))
+        }
+        NodeType::Default(body) => Node::new(NodeType::Default(Box::new(walk(*body)))),
+        NodeType::Label(name, body) => Node::new(NodeType::Label(name, Box::new(walk(*body)))),
+        NodeType::Break | NodeType::Goto(_) | NodeType::Null => node,
+        NodeType::Return(expr) => {
+            let ty = RETURN_TYPE.lock().unwrap().clone();
+            let expr = convert(walk(*expr), &ty);
+            Node::new(NodeType::Return(Box::new(expr)))
+        }
+        NodeType::ExprStmt(expr) => Node::new(NodeType::ExprStmt(Box::new(walk(*expr)))),
+        NodeType::CompStmt(stmts) => {
+            enter_scope();
+            let stmts = stmts.into_iter().map(walk).collect();
+            leave_scope();
+            Node::new(NodeType::CompStmt(stmts))
+        }
+        NodeType::InitList(_) => panic!("unexpected initializer list"),
+        // Types were resolved by the parser.
+        NodeType::Typedef(_) | NodeType::TypeDecl => Node::new(NodeType::CompStmt(vec![])),
+        NodeType::Lvar(_) | NodeType::Gvar(_) | NodeType::Cast(_) | NodeType::Func(..) => {
+            unreachable!()
+        }
+    }
+}
+
+fn function(name: String, params: Vec<Node>, body: Node, ty: Type) -> Function {
+    if params.len() > MAX_ARGS {
+        panic!("too many parameters in {}: {}", name, params.len());
+    }
+    if ty.is_struct() {
+        panic!("returning a struct by value is not supported: {}", name);
+    }
+    if FUNCS
+        .lock()
+        .unwrap()
+        .insert(name.clone(), ty.clone())
+        .is_some()
+    {
+        panic!("redefinition of {}", name);
+    }
+    *RETURN_TYPE.lock().unwrap() = ty;
+    *STACKSIZE.lock().unwrap() = 0;
+
+    enter_scope();
+    let params = params
+        .into_iter()
+        .map(|param| match param.ty {
+            NodeType::Vardef(name, _) => add_local(name, param.ctype),
+            _ => unreachable!(),
+        })
+        .collect();
+    let body = walk(body);
+    leave_scope();
+
+    Function {
+        name,
+        params,
+        body,
+        stacksize: roundup(*STACKSIZE.lock().unwrap(), 16),
+    }
+}
+
+fn global_var(name: String, ty: Type, init: Option<Node>) -> Global {
+    let data = init.map(|init| global_init(&name, &ty, Some(init)));
+    add_var(name.clone(), Var::Global(ty.clone()));
+    Global {
+        name,
+        size: ty.size(),
+        align: ty.align(),
+        data,
+        readonly: false,
+    }
+}
+
+// Resolves variables, computes the type of every expression and inserts
+// implicit conversions.
+pub fn sema(nodes: Vec<Node>) -> Program {
+    *SCOPES.lock().unwrap() = vec![HashMap::new()];
+    FUNCS.lock().unwrap().clear();
+    GLOBALS.lock().unwrap().clear();
+    *STR_LABEL.lock().unwrap() = 0;
+
+    let mut funcs = vec![];
+    for node in nodes {
+        match node.ty {
+            NodeType::Func(name, params, body) => {
+                funcs.push(function(name, params, *body, node.ctype))
+            }
+            NodeType::Vardef(name, init) => {
+                let var = global_var(name, node.ctype, init.map(|init| *init));
+                GLOBALS.lock().unwrap().push(var);
+            }
+            NodeType::Typedef(_) | NodeType::TypeDecl => (),
+            _ => unreachable!(),
+        }
+    }
+
+    Program {
+        funcs,
+        globals: GLOBALS.lock().unwrap().clone(),
+    }
+}
@@ -1,18 +1,214 @@
+use std::{cell::OnceCell, collections::HashMap, fmt, num::IntErrorKind, sync::Mutex};
+
+use lazy_static::lazy_static;
+
+use crate::preprocess::preprocess;
+
+lazy_static! {
+    static ref INTERNER: Mutex<HashMap<String, u32>> = Mutex::new(HashMap::new());
+}
+
+// A number which is the same for every occurrence of a name.
+pub fn intern(name: &str) -> u32 {
+    let mut interner = INTERNER.lock().unwrap();
+    if let Some(id) = interner.get(name) {
+        return *id;
+    }
+    let id = interner.len() as u32;
+    interner.insert(name.to_string(), id);
+    id
+}
+
+// An identifier. Its name is borrowed from the source, and it is only
+// interned when it is first looked up, so neither scanning nor reading
+// the name takes the interner's lock.
+#[derive(Clone)]
+pub struct Symbol {
+    name: &'static str,
+    id: OnceCell<u32>,
+}
+
+impl Symbol {
+    pub fn new(name: &'static str) -> Self {
+        Symbol {
+            name,
+            id: OnceCell::new(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn id(&self) -> u32 {
+        *self.id.get_or_init(|| intern(self.name))
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
-    Num(i32),      // Number literal
-    Ident(String), // Identifier
+    Num(i64),      // Number literal
+    Str(Vec<u8>),  // String literal
+    Ident(Symbol), // Identifier
     Plus,          // +
     Minus,         // -
     Mul,           // *
     Div,           // /
+    Mod,           // %
+    And,           // &
+    Or,            // |
+    Xor,           // ^
+    Shl,           // <<
+    Shr,           // >>
+    Inc,           // ++
+    Dec,           // --
+    AddEq,         // +=
+    SubEq,         // -=
+    MulEq,         // *=
+    DivEq,         // /=
+    ModEq,         // %=
+    ShlEq,         // <<=
+    ShrEq,         // >>=
+    AndEq,         // &=
+    OrEq,          // |=
+    XorEq,         // ^=
     If,            // if
     Else,          // else
     Return,        // return
+    Int,           // int
+    Char,          // char
+    Short,         // short
+    Long,          // long
+    Sizeof,        // sizeof
+    Struct,        // struct
+    Union,         // union
+    Enum,          // enum
+    Typedef,       // typedef
+    Switch,        // switch
+    Case,          // case
+    Default,       // default
+    Break,         // break
+    Goto,          // goto
+    Colon,         // :
+    Question,      // ?
     Semicolon,     // ;
-    LeftParen,     // ()
+    LeftParen,     // (
     RightParen,    // )
+    LeftBracket,   // [
+    RightBracket,  // ]
+    LeftBrace,     // {
+    RightBrace,    // }
     Equal,         // =
+    Comma,         // ,
+    Dot,           // .
+    Arrow,         // ->
+    Not,           // !
+    Tilde,         // ~
+    Lt,            // <
+    Gt,            // >
+    Le,            // <=
+    Ge,            // >=
+    EqEq,          // ==
+    Ne,            // !=
+    LogAnd,        // &&
+    LogOr,         // ||
+    Hash,          // #
+    HashHash,      // ##
+    Eof,           // End of input
+}
+
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use TokenType::*;
+        match self {
+            Num(val) => write!(f, "{}", val),
+            Str(_) => f.write_str("string literal"),
+            Ident(name) => write!(f, "{}", name),
+            Plus => f.write_str("+"),
+            Minus => f.write_str("-"),
+            Mul => f.write_str("*"),
+            Div => f.write_str("/"),
+            Mod => f.write_str("%"),
+            And => f.write_str("&"),
+            Or => f.write_str("|"),
+            Xor => f.write_str("^"),
+            Shl => f.write_str("<<"),
+            Shr => f.write_str(">>"),
+            Inc => f.write_str("++"),
+            Dec => f.write_str("--"),
+            AddEq => f.write_str("+="),
+            SubEq => f.write_str("-="),
+            MulEq => f.write_str("*="),
+            DivEq => f.write_str("/="),
+            ModEq => f.write_str("%="),
+            ShlEq => f.write_str("<<="),
+            ShrEq => f.write_str(">>="),
+            AndEq => f.write_str("&="),
+            OrEq => f.write_str("|="),
+            XorEq => f.write_str("^="),
+            If => f.write_str("if"),
+            Else => f.write_str("else"),
+            Return => f.write_str("return"),
+            Int => f.write_str("int"),
+            Char => f.write_str("char"),
+            Short => f.write_str("short"),
+            Long => f.write_str("long"),
+            Sizeof => f.write_str("sizeof"),
+            Struct => f.write_str("struct"),
+            Union => f.write_str("union"),
+            Enum => f.write_str("enum"),
+            Typedef => f.write_str("typedef"),
+            Switch => f.write_str("switch"),
+            Case => f.write_str("case"),
+            Default => f.write_str("default"),
+            Break => f.write_str("break"),
+            Goto => f.write_str("goto"),
+            Colon => f.write_str(":"),
+            Question => f.write_str("?"),
+            Semicolon => f.write_str(";"),
+            LeftParen => f.write_str("("),
+            RightParen => f.write_str(")"),
+            LeftBracket => f.write_str("["),
+            RightBracket => f.write_str("]"),
+            LeftBrace => f.write_str("{"),
+            RightBrace => f.write_str("}"),
+            Equal => f.write_str("="),
+            Comma => f.write_str(","),
+            Dot => f.write_str("."),
+            Arrow => f.write_str("->"),
+            Not => f.write_str("!"),
+            Tilde => f.write_str("~"),
+            Lt => f.write_str("<"),
+            Gt => f.write_str(">"),
+            Le => f.write_str("<="),
+            Ge => f.write_str(">="),
+            EqEq => f.write_str("=="),
+            Ne => f.write_str("!="),
+            LogAnd => f.write_str("&&"),
+            LogOr => f.write_str("||"),
+            Hash => f.write_str("#"),
+            HashHash => f.write_str("##"),
+            Eof => f.write_str("end of input"),
+        }
+    }
 }
 
 impl From<char> for TokenType {
@@ -23,108 +219,436 @@ impl From<char> for TokenType {
             '-' => Minus,
             '*' => Mul,
             '/' => Div,
+            '%' => Mod,
+            '&' => And,
+            '|' => Or,
+            '^' => Xor,
             ';' => Semicolon,
+            ':' => Colon,
+            '?' => Question,
             '=' => Equal,
+            ',' => Comma,
+            '.' => Dot,
             '(' => LeftParen,
             ')' => RightParen,
+            '[' => LeftBracket,
+            ']' => RightBracket,
+            '{' => LeftBrace,
+            '}' => RightBrace,
+            '!' => Not,
+            '~' => Tilde,
+            '<' => Lt,
+            '>' => Gt,
+            '#' => Hash,
             e => panic!("unknown token type: {}", e),
         }
     }
 }
 
-impl From<String> for TokenType {
-    fn from(s: String) -> Self {
-        match &*s {
+impl TokenType {
+    // Operators like `+=` which combine an arithmetic operation with an
+    // assignment.
+    pub fn is_compound_assign(&self) -> bool {
+        use TokenType::*;
+        matches!(
+            self,
+            AddEq | SubEq | MulEq | DivEq | ModEq | ShlEq | ShrEq | AndEq | OrEq | XorEq
+        )
+    }
+
+    // Operators which yield 0 or 1: relational, equality and logical ones.
+    pub fn is_comparison(&self) -> bool {
+        use TokenType::*;
+        matches!(self, Lt | Gt | Le | Ge | EqEq | Ne | LogAnd | LogOr)
+    }
+}
+
+impl From<&'static str> for TokenType {
+    fn from(s: &'static str) -> Self {
+        match s {
             "return" => TokenType::Return,
             "if" => TokenType::If,
             "else" => TokenType::Else,
-            name => TokenType::Ident(name.to_string()),
+            "int" => TokenType::Int,
+            "char" => TokenType::Char,
+            "short" => TokenType::Short,
+            "long" => TokenType::Long,
+            "sizeof" => TokenType::Sizeof,
+            "struct" => TokenType::Struct,
+            "union" => TokenType::Union,
+            "enum" => TokenType::Enum,
+            "typedef" => TokenType::Typedef,
+            "switch" => TokenType::Switch,
+            "case" => TokenType::Case,
+            "default" => TokenType::Default,
+            "break" => TokenType::Break,
+            "goto" => TokenType::Goto,
+            name => TokenType::Ident(Symbol::new(name)),
         }
     }
 }
 
-#[derive(Debug)]
+// Longer symbols come first so that `<<=` is not read as `<<` and `=`.
+const SYMBOLS: [(&str, TokenType); 22] = [
+    ("<<=", TokenType::ShlEq),
+    (">>=", TokenType::ShrEq),
+    ("->", TokenType::Arrow),
+    ("++", TokenType::Inc),
+    ("--", TokenType::Dec),
+    ("+=", TokenType::AddEq),
+    ("-=", TokenType::SubEq),
+    ("*=", TokenType::MulEq),
+    ("/=", TokenType::DivEq),
+    ("%=", TokenType::ModEq),
+    ("&=", TokenType::AndEq),
+    ("|=", TokenType::OrEq),
+    ("^=", TokenType::XorEq),
+    ("<<", TokenType::Shl),
+    (">>", TokenType::Shr),
+    ("<=", TokenType::Le),
+    (">=", TokenType::Ge),
+    ("==", TokenType::EqEq),
+    ("!=", TokenType::Ne),
+    ("&&", TokenType::LogAnd),
+    ("||", TokenType::LogOr),
+    ("##", TokenType::HashHash),
+];
+
+// A range of bytes in a source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone)]
 pub struct Token {
     pub ty: TokenType,
-    pub input: String,
+
+    // The source the token was read from, and where in it
+    pub src: &'static str,
+    pub span: Span,
+    pub line: usize,
+    // Whether the token is the first one on its line
+    pub bol: bool,
+    // Whether the token is preceded by whitespace
+    pub space: bool,
+}
+
+impl Token {
+    // The token as written in the source
+    pub fn text(&self) -> &'static str {
+        &self.src[self.span.start..self.span.end]
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ty {
+            TokenType::Eof => f.write_str("end of input"),
+            _ => f.write_str(self.text()),
+        }
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {:?} at line {}", self.ty, self.text(), self.line)
+    }
+}
+
+// A comment as written in the source. The formatter also keeps
+// preprocessor directive lines this way, since it doesn't expand them.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: &'static str,
+    pub span: Span,
+    pub directive: bool,
+}
+
+// Sources are kept until the compiler exits, so that tokens and
+// identifiers can refer to them instead of copying.
+pub fn new_source(src: String) -> &'static str {
+    Box::leak(src.into_boxed_str())
 }
 
-pub fn scan(mut p: String) -> Vec<Token> {
+pub fn scan(src: &'static str) -> Vec<Token> {
+    scan_with_comments(src).0
+}
+
+// Splits a source into tokens, and returns the comments between them too.
+pub fn scan_with_comments(src: &'static str) -> (Vec<Token>, Vec<Comment>) {
     let mut tokens: Vec<Token> = vec![];
+    let mut comments = vec![];
+    let mut pos = 0;
+    let mut line = 1;
+    let mut bol = true;
+    let mut space = false;
 
-    let org = p.clone();
-    while let Some(c) = p.chars().next() {
-        if c.is_whitespace() {
-            p = p.split_off(1);
+    while let Some(c) = src[pos..].chars().next() {
+        let rest = &src[pos..];
+        if c == '\n' {
+            pos += 1;
+            line += 1;
+            bol = true;
             continue;
         }
 
-        // Single-letter tokens
-        match c {
-            '+' | '-' | '*' | '/' | ';' | '=' | '(' | ')' => {
-                let token = Token {
-                    ty: TokenType::from(c),
-                    input: org.clone(),
-                };
-                p = p.split_off(1);
-                tokens.push(token);
-                continue;
-            }
-            _ => (),
+        if rest.starts_with("//") {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            comments.push(new_comment(src, pos, pos + len));
+            pos += len;
+            space = true;
+            continue;
         }
 
-        // Identifier
-        if c.is_alphabetic() || c == '_' {
-            let mut name = String::new();
-            while let Some(c2) = p.chars().next() {
-                if c2.is_alphabetic() || c2.is_ascii_digit() || c2 == '_' {
-                    p = p.split_off(1);
-                    name.push(c2);
-                    continue;
+        if let Some(body) = rest.strip_prefix("/*") {
+            let len = match body.find("*/") {
+                Some(len) => len + 4,
+                None => {
+                    let col = pos - src[..pos].rfind('\n').map_or(0, |i| i + 1) + 1;
+                    panic!("unterminated comment at line {}, column {}", line, col);
                 }
-                break;
-            }
-            let token = Token {
-                ty: TokenType::from(name),
-                input: org.clone(),
             };
-            tokens.push(token);
+            line += rest[..len].matches('\n').count();
+            comments.push(new_comment(src, pos, pos + len));
+            pos += len;
+            space = true;
             continue;
         }
 
-        if c.is_ascii_digit() {
-            let n = strtol(&mut p);
-            let token = Token {
-                ty: TokenType::Num(n.unwrap() as i32),
-                input: org.clone(),
-            };
-            tokens.push(token);
+        // A backslash at the end of a line joins it with the next one.
+        if rest.starts_with("\\\n") {
+            pos += 2;
+            line += 1;
+            space = true;
             continue;
         }
 
-        panic!("cannot tokenize: {}", p);
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            space = true;
+            continue;
+        }
+
+        let start = pos;
+        let ty = read_token(src, &mut pos, line);
+        tokens.push(Token {
+            ty,
+            src,
+            span: Span { start, end: pos },
+            line,
+            bol,
+            space: space || bol,
+        });
+        bol = false;
+        space = false;
     }
-    tokens
+    (tokens, comments)
 }
 
-pub fn tokenize(p: String) -> Vec<Token> {
-    scan(p)
+fn new_comment(src: &'static str, start: usize, end: usize) -> Comment {
+    // A line comment doesn't own the whitespace before the newline.
+    let end = start + src[start..end].trim_end().len();
+    Comment {
+        text: &src[start..end],
+        span: Span { start, end },
+        directive: false,
+    }
 }
 
-fn strtol(s: &mut String) -> Option<i64> {
-    if s.is_empty() {
-        return None;
+// Reads one token starting at `pos`.
+fn read_token(src: &'static str, pos: &mut usize, line: usize) -> TokenType {
+    let rest = &src[*pos..];
+    let c = rest.chars().next().unwrap();
+
+    // Multi-letter tokens
+    if let Some((sym, ty)) = SYMBOLS.iter().find(|(sym, _)| rest.starts_with(sym)) {
+        *pos += sym.len();
+        return ty.clone();
     }
 
-    let mut pos = 0;
+    // Single-letter tokens
+    match c {
+        '+' | '-' | '*' | '/' | '%' | '&' | '|' | '^' | ';' | ':' | '?' | '=' | '(' | ')' | '['
+        | ']' | '{' | '}' | ',' | '.' | '!' | '~' | '<' | '>' | '#' => {
+            *pos += 1;
+            return TokenType::from(c);
+        }
+        _ => (),
+    }
+
+    // String literal
+    if c == '"' {
+        *pos += 1;
+        let mut bytes = vec![];
+        loop {
+            match src[*pos..].chars().next() {
+                Some('"') => break,
+                Some('\\') => {
+                    *pos += 1;
+                    bytes.push(read_escaped(src, pos));
+                }
+                Some('\n') | None => panic!("unclosed string literal at line {}", line),
+                Some(c2) => {
+                    let len = c2.len_utf8();
+                    bytes.extend_from_slice(&src.as_bytes()[*pos..*pos + len]);
+                    *pos += len;
+                }
+            }
+        }
+        *pos += 1;
+        return TokenType::Str(bytes);
+    }
 
-    for c in s.chars() {
-        if !c.is_ascii_digit() {
-            break;
+    // Character literal
+    if c == '\'' {
+        *pos += 1;
+        let b = match src[*pos..].chars().next() {
+            Some('\\') => {
+                *pos += 1;
+                read_escaped(src, pos)
+            }
+            Some('\'') => panic!("empty character literal at line {}", line),
+            Some(c2) if c2.is_ascii() && c2 != '\n' => {
+                *pos += 1;
+                c2 as u8
+            }
+            Some('\n') | None => panic!("unclosed character literal at line {}", line),
+            Some(c2) => panic!("character too large for char literal: {}", c2),
+        };
+        if !src[*pos..].starts_with('\'') {
+            panic!("unclosed character literal at line {}", line);
         }
-        pos += 1;
+        *pos += 1;
+
+        // char is signed, so '\xff' is -1.
+        return TokenType::Num(b as i8 as i64);
+    }
+
+    // Identifier
+    if c.is_alphabetic() || c == '_' {
+        let len = rest
+            .find(|c2: char| !(c2.is_alphabetic() || c2.is_ascii_digit() || c2 == '_'))
+            .unwrap_or(rest.len());
+        *pos += len;
+        return TokenType::from(&rest[..len]);
+    }
+
+    if c.is_ascii_digit() {
+        return TokenType::Num(strtol(src, pos));
     }
 
-    let t = s.drain(..pos).collect::<String>();
-    Some(t.parse::<i64>().unwrap())
+    panic!("cannot tokenize at line {}: {}", line, c);
+}
+
+// The token stream always ends with an Eof token, so that the parser can
+// look ahead without running off the end.
+pub fn tokenize(p: String, path: &str) -> Vec<Token> {
+    let mut tokens = preprocess(scan(new_source(p)), path);
+    push_eof(&mut tokens);
+    tokens
+}
+
+pub fn push_eof(tokens: &mut Vec<Token>) {
+    let line = tokens.last().map_or(1, |t| t.line);
+    tokens.push(Token {
+        ty: TokenType::Eof,
+        src: "",
+        span: Span { start: 0, end: 0 },
+        line,
+        bol: true,
+        space: false,
+    });
+}
+
+// Reads the character after a backslash and returns the byte it denotes.
+fn read_escaped(src: &str, pos: &mut usize) -> u8 {
+    let rest = &src[*pos..];
+    let c = match rest.chars().next() {
+        Some(c) => c,
+        None => panic!("premature end of input"),
+    };
+
+    // Octal escape: \0, \12, \177
+    if ('0'..='7').contains(&c) {
+        let len = rest
+            .chars()
+            .take(3)
+            .take_while(|c| ('0'..='7').contains(c))
+            .count();
+        let digits = &rest[..len];
+        *pos += len;
+        return match u8::from_str_radix(digits, 8) {
+            Ok(b) => b,
+            Err(_) => panic!("octal escape sequence out of range: \\{}", digits),
+        };
+    }
+
+    // Hexadecimal escape: \x41
+    if c == 'x' {
+        let rest = &rest[1..];
+        let len = rest.chars().take_while(|c| c.is_ascii_hexdigit()).count();
+        if len == 0 {
+            panic!("\\x used with no following hex digits");
+        }
+        let digits = &rest[..len];
+        *pos += len + 1;
+        return match u8::from_str_radix(digits, 16) {
+            Ok(b) => b,
+            Err(_) => panic!("hex escape sequence out of range: \\x{}", digits),
+        };
+    }
+
+    *pos += c.len_utf8();
+    match c {
+        'a' => 7,
+        'b' => 8,
+        't' => b'\t',
+        'n' => b'\n',
+        'v' => 11,
+        'f' => 12,
+        'r' => b'\r',
+        'e' => 27,
+        c if c.is_ascii() => c as u8,
+        c => panic!("unknown escape sequence: \\{}", c),
+    }
+}
+
+// Reads an integer literal. A leading 0x is hexadecimal, 0b is binary and a
+// leading 0 is octal.
+fn strtol(src: &str, pos: &mut usize) -> i64 {
+    let rest = &src[*pos..];
+    let len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(rest.len());
+    let t = &rest[..len];
+    *pos += len;
+
+    let lower = t.to_ascii_lowercase();
+    let (radix, digits) = if let Some(digits) = lower.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        (2, digits)
+    } else if lower.len() > 1 && lower.starts_with('0') {
+        (8, &lower[1..])
+    } else {
+        (10, &lower[..])
+    };
+
+    // A hexadecimal, octal or binary literal may use all 64 bits, as in
+    // 0xFFFFFFFFFFFFFFFF, and wraps to a negative value. A decimal one
+    // must fit in a long.
+    match u64::from_str_radix(digits, radix) {
+        Ok(n) if radix != 10 => n as i64,
+        Ok(n) => match i64::try_from(n) {
+            Ok(n) => n,
+            Err(_) => panic!("integer literal is too large: {}", t),
+        },
+        Err(e) => match e.kind() {
+            IntErrorKind::PosOverflow => panic!("integer literal is too large: {}", t),
+            IntErrorKind::Empty => panic!("no digits in integer literal: {}", t),
+            _ => panic!("invalid digit in integer literal: {}", t),
+        },
+    }
 }
@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+
+use crate::ir::{Function, VReg, IR};
+use crate::REGS_N;
+
+// Registers an instruction reads and writes, in that order. Sign
+// extensions read their lhs before overwriting it.
+fn operands(ir: &IR) -> (Vec<VReg>, Option<VReg>) {
+    use IR::*;
+
+    match ir {
+        Imm { lhs, .. } | Alloca { lhs, .. } | LabelAddr { lhs, .. } => (vec![], Some(*lhs)),
+        Mov { lhs, rhs }
+        | Load8 { lhs, rhs }
+        | Load16 { lhs, rhs }
+        | Load32 { lhs, rhs }
+        | Load64 { lhs, rhs } => (vec![*rhs], Some(*lhs)),
+        Add { dst, lhs, rhs }
+        | Sub { dst, lhs, rhs }
+        | Mul { dst, lhs, rhs }
+        | Div { dst, lhs, rhs }
+        | Mod { dst, lhs, rhs }
+        | Shl { dst, lhs, rhs }
+        | Shr { dst, lhs, rhs }
+        | And { dst, lhs, rhs }
+        | Or { dst, lhs, rhs }
+        | Xor { dst, lhs, rhs }
+        | Eq { dst, lhs, rhs }
+        | Ne { dst, lhs, rhs }
+        | Lt { dst, lhs, rhs }
+        | Le { dst, lhs, rhs } => (vec![*lhs, *rhs], Some(*dst)),
+        AddImm { dst, lhs, .. } => (vec![*lhs], Some(*dst)),
+        Sext8 { lhs } | Sext16 { lhs } | Sext32 { lhs } => (vec![*lhs], Some(*lhs)),
+        Store8 { lhs, rhs }
+        | Store16 { lhs, rhs }
+        | Store32 { lhs, rhs }
+        | Store64 { lhs, rhs } => (vec![*lhs, *rhs], None),
+        StoreArg8 { lhs, .. }
+        | StoreArg16 { lhs, .. }
+        | StoreArg32 { lhs, .. }
+        | StoreArg64 { lhs, .. }
+        | Unless { lhs, .. }
+        | JumpTable { lhs, .. }
+        | Return { lhs } => (vec![*lhs], None),
+        Call { lhs, args, .. } => (args.clone(), Some(*lhs)),
+        Label { .. } | Jmp { .. } | Kill { .. } | Nop => (vec![], None),
+    }
+}
+
+struct Verifier<'a> {
+    f: &'a Function,
+    pass: &'a str,
+    // Registers are numbered by alloc_regs, and Kills are gone.
+    physical: bool,
+}
+
+impl Verifier<'_> {
+    fn error(&self, i: usize, msg: String) -> ! {
+        panic!(
+            "invalid IR after {}: {} at instruction {} of {}",
+            self.pass, msg, i, self.f.name
+        );
+    }
+
+    fn run(&self) {
+        let irv = &self.f.irv;
+
+        // Operands are typed, so only the argument count and slots are
+        // left to check.
+        for (i, ir) in irv.iter().enumerate() {
+            match ir {
+                IR::Call { args, .. } if args.len() > 6 => {
+                    self.error(i, format!("{} arguments, but at most 6", args.len()));
+                }
+                IR::StoreArg8 { slot, .. }
+                | IR::StoreArg16 { slot, .. }
+                | IR::StoreArg32 { slot, .. }
+                | IR::StoreArg64 { slot, .. }
+                    if slot.0 >= 6 =>
+                {
+                    self.error(i, format!("argument slot {}, but at most 5", slot.0));
+                }
+                _ => (),
+            }
+        }
+
+        let mut labels = HashSet::new();
+        for (i, ir) in irv.iter().enumerate() {
+            if let IR::Label { label } = ir {
+                if !labels.insert(*label) {
+                    self.error(i, format!("label {} defined twice", label));
+                }
+            }
+        }
+        for (i, ir) in irv.iter().enumerate() {
+            let targets = match ir {
+                IR::Jmp { label } | IR::Unless { label, .. } => vec![*label],
+                IR::JumpTable {
+                    default, targets, ..
+                } => [*default].into_iter().chain(targets.clone()).collect(),
+                _ => vec![],
+            };
+            for l in targets {
+                if !labels.contains(&l) {
+                    self.error(i, format!("jump to undefined label {}", l));
+                }
+            }
+        }
+
+        // Registers are checked in program order, which is the order
+        // alloc_regs assigns them in.
+        let mut defined = HashSet::new();
+        let mut killed = HashSet::new();
+        for (i, ir) in irv.iter().enumerate() {
+            let (uses, def) = operands(ir);
+            for r in uses.iter().chain(def.iter()) {
+                if self.physical && r.0 >= REGS_N {
+                    self.error(i, format!("{} is not a machine register", r));
+                }
+            }
+            for r in uses.iter() {
+                if killed.contains(r) {
+                    self.error(i, format!("{} used after it is killed", r));
+                }
+                if !defined.contains(r) {
+                    self.error(i, format!("{} used before it is defined", r));
+                }
+            }
+
+            match ir {
+                IR::Kill { .. } if self.physical => {
+                    self.error(i, "KILL after register allocation".to_string())
+                }
+                IR::Kill { lhs } => {
+                    if !defined.contains(lhs) {
+                        self.error(i, format!("{} killed before it is defined", lhs));
+                    }
+                    if !killed.insert(*lhs) {
+                        self.error(i, format!("{} killed twice", lhs));
+                    }
+                }
+                // A call consumes its arguments.
+                IR::Call { args, .. } if !self.physical => killed.extend(args.iter()),
+                _ => (),
+            }
+
+            if let Some(r) = def {
+                if killed.contains(&r) {
+                    self.error(i, format!("{} defined after it is killed", r));
+                }
+                defined.insert(r);
+            }
+        }
+
+        match irv
+            .iter()
+            .rev()
+            .find(|ir| !matches!(ir, IR::Kill { .. } | IR::Nop))
+        {
+            Some(IR::Return { .. }) => (),
+            _ => self.error(irv.len(), "function does not end in a return".to_string()),
+        }
+    }
+}
+
+// Checks the IR on virtual registers, as gen_ir and parse_ir produce it,
+// and panics at the first problem. `pass` names what made the IR.
+pub fn verify(fns: &[Function], pass: &str) {
+    for f in fns {
+        let v = Verifier {
+            f,
+            pass,
+            physical: false,
+        };
+        v.run();
+    }
+}
+
+// Checks the IR after alloc_regs, or a pass after it.
+pub fn verify_allocated(fns: &[Function], pass: &str) {
+    for f in fns {
+        let v = Verifier {
+            f,
+            pass,
+            physical: true,
+        };
+        v.run();
+    }
+}
@@ -16,12 +16,30 @@ try() {
   fi
 }
 
+fail() {
+  expected="$1"
+  input="$2"
+
+  if output=$(./target/x86_64-unknown-linux-musl/debug/mona "$input" 2>&1); then
+    echo "$input => error expected, but it compiled"
+    exit 1
+  fi
+
+  if [[ "$output" == *"$expected"* ]]; then
+    echo "$input => $expected"
+  else
+    echo "$input => $expected expected, but got $output"
+    exit 1
+  fi
+}
+
 # add, sub
 try 0 'return 0;'
 try 42 'return 42;'
 try 21 'return 5+20-4;'
 try 41 'return 12 + 34 -5 ;'
 try 153 'return 1+2+3+4+5+6+7+8+9+10+11+12+13+14+15+16+17;'
+try 28 'int main() { return 1+(2+(3+(4+(5+(6+7))))); }'
 
 # mul, div
 try 10 'return 2*3+4;'
@@ -31,8 +49,99 @@ try 5 'return 50/10;'
 try 9 'return 6*3/2;'
 
 # variable
-try 2 'a=2; return a;'
-try 10 'a=2; b=3+2; return a*b;'
+try 2 'int a=2; return a;'
+try 10 'int a=2; int b=3+2; return a*b;'
+try 7 'int a; a=7; return a;'
+
+# types
+try 3 'char c=3; return c;'
+try 44 'char c=300; return c;'
+try 255 'char c=255; long l=c+256; return l;'
+try 5 'short s=65541; return s;'
+try 1 'int a=65537; short b=a; return b;'
+try 6 'char a=1; short b=2; int c=3; return a+b+c;'
+try 9 'long x=9; int *p; char **q; return x;'
+
+# pointers
+try 3 'int x=3; int *p=&x; return *p;'
+try 5 'int x=3; int *p=&x; *p=5; return x;'
+try 9 'int x=3; int *p=&x; int **pp=&p; **pp=9; return x;'
+try 2 'int a[2]; a[0]=1; a[1]=2; int *p=a; return *(p+1);'
+try 7 'long a[2]; a[0]=1; a[1]=7; return *(&a[0]+1);'
+try 1 'char a[2]; a[0]=1; a[1]=2; return *(&a[1]-1);'
+try 1 'int a[2]; return &a[1]-&a[0];'
+try 1 'int a[2]; return &a[0]-&a[1]+2;'
+try 2 'long a[3]; return &a[2]-&a[0];'
+try 3 'int a[3]; a[0]=1; a[1]=2; a[2]=3; int *p=a; p=p+2; return *p;'
+try 4 'short a[2]; a[1]=4; short *p=a; return *(1+p);'
+
+# arrays
+try 3 'int a[2]; *a=1; *(a+1)=2; int *p=a; return *p+*(p+1);'
+try 5 'int a[3]; a[0]=1; a[1]=2; a[2]=3; return a[0]+a[2]+1;'
+try 8 'char s[4]; s[3]=8; return s[3];'
+try 6 'int a[2][3]; a[1][2]=6; return a[1][2];'
+try 5 'int a[2][3]; int *p=a[1]; p[1]=5; return a[1][1];'
+try 2 'long a[4]; a[2]=2; return 2[a];'
+try 12 'int x; int a[3]; return &a[2]-&a[0]+10;'
+
+# sizeof
+try 4 'return sizeof(int);'
+try 1 'return sizeof(char);'
+try 2 'return sizeof(short);'
+try 8 'return sizeof(long);'
+try 8 'return sizeof(int *);'
+try 40 'return sizeof(int[10]);'
+try 4 'int x; return sizeof(x);'
+try 4 'int x; return sizeof x;'
+try 8 'int *p; return sizeof(p);'
+try 4 'int *p; return sizeof(*p);'
+try 40 'int a[10]; return sizeof(a);'
+try 24 'int a[2][3]; return sizeof(a);'
+try 12 'int a[2][3]; return sizeof(a[1]);'
+try 8 'int a[10]; return sizeof(a+1);'
+try 8 'int x; return sizeof(x+sizeof(x));'
+
+# string literals
+try 97 'return "abc"[0];'
+try 99 'return "abc"[2];'
+try 0 'return "abc"[3];'
+try 4 'return sizeof("abc");'
+try 10 'return "\n"[0];'
+try 34 'return "\""[0];'
+try 92 'return "\\"[0];'
+try 65 'return "\x41"[0];'
+try 8 'return "\10"[0];'
+try 0 'return "a\0b"[1];'
+try 98 'char *s="abc"; return *(s+1);'
+try 6 'return printf("hello\n");'
+try 5 'return printf("%d%s\n", 42, "ab");'
+try 0 'puts("hello"); return 0;'
+try 1 'return puts("hello") >= 0;'
+
+# character literals
+try 97 "return 'a';"
+try 10 "return '\\n';"
+try 0 "return '\\0';"
+try 65 "return '\\x41';"
+try 39 "return '\\'';"
+try 92 "return '\\\\';"
+try 255 "return '\\xff';"
+try 4 "return sizeof('a');"
+try 98 "char c='b'; return c;"
+
+# integer literals
+try 31 'return 0x1F;'
+try 31 'return 0X1f;'
+try 15 'return 017;'
+try 5 'return 0b101;'
+try 0 'return 0;'
+try 8 'return sizeof(4294967296);'
+try 4 'return sizeof(2147483647);'
+try 1 'long x=4294967297; int y=x; return y;'
+try 2 'long x=9223372036854775807; return x/4611686018427387903;'
+try 255 'int main() { return 0xFFFFFFFFFFFFFFFF; }'
+try 1 'int main() { long x=0x8000000000000000; return x<0; }'
+try 1 'int main() { long x=01777777777777777777777; return x==-1; }'
 
 # ()
 try 45 'return (2+3)*(4+5);'
@@ -45,4 +154,522 @@ try 3 'if (0) return 2; return 3;'
 try 2 'if (1) return 2; else return 3;'
 try 3 'if (0) return 2; else return 3;'
 
+# blocks
+try 3 'int main() { int a=1; { int a=2; a=3; } { int b=2; a=a+b; } return a; }'
+try 5 'int main() { { return 5; } }'
+
+# functions
+try 3 'int ret3() { return 3; } int main() { return ret3(); }'
+try 5 'int add(int a, int b) { return a+b; } int main() { return add(2, 3); }'
+try 21 'int add6(int a, int b, int c, int d, int e, int f) { return a+b+c+d+e+f; } int main() { return add6(1,2,3,4,5,6); }'
+try 22 'int add6(int a, int b, int c, int d, int e, int f) { return a+b+c+d+e+f; } int main() { int x=1; return x + add6(1,2,3,4,5,6); }'
+try 8 'int f(int a,int b,int c,int d,int e,int g){return a+g;} int main(){ return f(1,2,3,4,5,f(1,2,3,4,5,6)); }'
+try 11 'int f(int a,int b,int c,int d,int e,int g){return a+g;} int main(){ return f(1,2,3,4,5,f(1,2,3,4,5,f(1,2,3,4,5,f(1,2,3,4,5,f(6,5,4,3,2,1))))); }'
+try 7 'int sub(char a, short b) { return a-b; } int main() { return sub(10, 3); }'
+try 8 'int fib(int n) { if (n-1) if (n-2) return fib(n-1)+fib(n-2); return 1; } int main() { return fib(6); }'
+try 4 'int swap(int *a, int *b) { int t=*a; *a=*b; *b=t; return 0; } int main() { int x=3; int y=4; swap(&x, &y); return x; }'
+try 4 'char *second(char *s) { return s+1; } int main() { return *second("x\4"); }'
+try 1 'long big() { return 4294967297; } int main() { long x=big(); int y=x; return y; }'
+
+# local initializers
+try 6 'int main() { int a[3]={1,2,3}; return a[0]+a[1]+a[2]; }'
+try 0 'int main() { int a[3]={1}; return a[2]; }'
+try 5 'int main() { int a[2][2]={{1,2},{3,4}}; return a[0][0]+a[1][1]; }'
+try 99 'int main() { char s[4]="abc"; return s[2]; }'
+try 0 'int main() { char s[4]="ab"; return s[3]; }'
+
+# global variables
+try 0 'int g; int main() { return g; }'
+try 5 'int g=5; int main() { return g; }'
+try 7 'int g; int main() { g=7; return g; }'
+try 9 'int g=2*3+3; int main() { return g; }'
+try 4 'int g=sizeof(long)/2; int main() { return g; }'
+try 10 'int t[4]={1,2,3,4}; int main() { return t[0]+t[1]+t[2]+t[3]; }'
+try 3 'int t[4]={1,2,3}; int main() { return t[2]+t[3]; }'
+try 0 'long t[100]; int main() { return t[99]; }'
+try 1 'char c=257; int main() { return c; }'
+try 6 'short s[2][2]={{1,2},{3}}; int main() { return s[0][0]+s[0][1]+s[1][0]+s[1][1]; }'
+try 98 'char s[4]="abc"; int main() { return s[1]; }'
+try 3 'int g=3; int *p=&g; int main() { return *p; }'
+try 100 'char *msg="hello, world"; int main() { return msg[11]; }'
+try 8 'int g=4; int twice() { g=g*2; return g; } int main() { twice(); return g; }'
+try 5 'int g=5; int main() { int g=1; return g+4; }'
+
+# structs and unions
+try 3 'int main() { struct { int a; int b; } x; x.a=1; x.b=2; return x.a+x.b; }'
+try 8 'int main() { struct { char a; int b; } x; return sizeof(x); }'
+try 16 'int main() { struct { char a; long b; } x; return sizeof(x); }'
+try 6 'int main() { struct { int a; char b; char c; } x; return sizeof(x)-2; }'
+try 12 'int main() { struct { char a[3]; int b[2]; } x; return sizeof(x); }'
+try 5 'int main() { struct { char a[3]; int b[2]; } x; x.a[2]=2; x.b[1]=3; return x.a[2]+x.b[1]; }'
+try 7 'struct point { int x; int y; }; int main() { struct point p; struct point *q=&p; q->y=7; return p.y; }'
+try 8 'struct point { int x; int y; }; int main() { return sizeof(struct point); }'
+try 3 'int main() { struct s { int v; struct s *next; } a; struct s b; a.next=&b; b.v=3; return a.next->v; }'
+try 32 'int main() { struct s { int v; struct s *next; } a; return sizeof(a)+sizeof(*a.next); }'
+try 6 'int main() { struct { struct { int a; int b; } in; int c; } x; x.in.b=6; return x.in.b; }'
+try 9 'struct p { int x; int y; }; int main() { struct p a; struct p b; a.x=4; a.y=5; b=a; return b.x+b.y; }'
+try 15 'struct p { char c; long l; short s; }; int main() { struct p a; struct p b; a.c=1; a.l=10; a.s=4; b=a; return b.c+b.l+b.s; }'
+try 4 'int main() { union { int a; char b[4]; } u; return sizeof(u); }'
+try 8 'int main() { union { int a; long b; char c[5]; } u; return sizeof(u); }'
+try 2 'int main() { union { int a; char b[4]; } u; u.a=515; return u.b[1]; }'
+try 1 'int main() { union { int a; char b[4]; } u; u.a=0; u.b[0]=1; return u.a; }'
+try 5 'struct p { int x; int y; }; int sum(struct p *p) { return p->x+p->y; } int main() { struct p a; a.x=2; a.y=3; return sum(&a); }'
+try 6 'struct p { int x; int y; }; struct p g; int main() { g.x=6; return g.x+g.y; }'
+try 7 'struct p { char c; int x; }; struct p g={3, 4}; int main() { return g.c+g.x; }'
+try 10 'struct p { int a; int b; }; struct p g[2]={{1,2},{3,4}}; int main() { return g[0].a+g[0].b+g[1].a+g[1].b; }'
+try 9 'union u { int a; char b; }; union u g={9}; int main() { return g.b; }'
+try 5 'struct p { int x; int y; }; int main() { struct p a={2, 3}; return a.x+a.y; }'
+try 0 'struct p { int x; int y; }; int main() { struct p a={2}; return a.y; }'
+try 7 'struct p { int x; int y; }; int main() { struct p a={3, 4}; struct p b=a; return b.x+b.y; }'
+
+# enums and typedefs
+try 0 'int main() { enum { A, B, C }; return A; }'
+try 2 'int main() { enum { A, B, C }; return C; }'
+try 17 'int main() { enum { A=5, B, C=B*2 }; return B+C-1; }'
+try 12 'enum color { RED, GREEN, BLUE }; int main() { enum color c=BLUE; return c*4+GREEN*0+sizeof(c); }'
+try 12 'enum { N=3 }; int main() { int a[N*2]; return sizeof(a)/2; }'
+try 4 'int main() { enum { A=4 }; { int A=7; } return A; }'
+try 3 'typedef int myint; int main() { myint x=3; return x; }'
+try 8 'int main() { typedef long L; return sizeof(L); }'
+try 5 'typedef int *intptr; int main() { int x=5; intptr p=&x; return *p; }'
+try 6 'typedef struct { int a; int b; } pair; int main() { pair p; p.a=2; p.b=4; return p.a+p.b; }'
+try 12 'typedef int triple[3]; int main() { triple t; return sizeof(t); }'
+try 9 'typedef int t; int main() { int t=9; return t; }'
+try 3 'typedef int t; int main() { { int t=1; } t x=3; return x; }'
+
+# switch
+try 5 'int main() { int x=2; switch (x) { case 1: return 4; case 2: return 5; } return 6; }'
+try 6 'int main() { int x=3; switch (x) { case 1: return 4; case 2: return 5; } return 6; }'
+try 7 'int main() { int x=9; switch (x) { case 1: return 4; default: return 7; case 2: return 5; } return 6; }'
+try 9 'int main() { int x=1; int y=0; switch (x) { case 1: y=y+4; case 2: y=y+5; break; case 3: y=y+6; } return y; }'
+try 3 'int main() { int x=1; int y=0; switch (x) { case 0: y=1; break; case 1: y=3; break; case 2: y=5; break; case 3: y=7; break; case 5: y=9; break; } return y; }'
+try 9 'int main() { int x=5; int y=0; switch (x) { case 0: y=1; break; case 1: y=3; break; case 2: y=5; break; case 3: y=7; break; case 5: y=9; break; } return y; }'
+try 2 'int main() { int x=4; int y=2; switch (x) { case 0: y=1; break; case 1: y=3; break; case 2: y=5; break; case 3: y=7; break; case 5: y=9; break; } return y; }'
+try 42 'int main() { char c=99; switch (c) { case 97: return 40; case 98: return 41; case 99: return 42; case 100: return 43; default: return 44; } }'
+try 44 'int main() { long x=3*1000000000; switch (x) { case 97: return 40; case 98: return 41; case 99: return 42; case 100: return 43; default: return 44; } }'
+try 8 'enum { A, B, C }; int main() { int y=0; switch (B) { case A: y=1; break; case B: switch (y) { case 0: y=8; break; case 1: y=9; } break; case C: y=3; } return y; }'
+try 11 'int main() { int x=1000; switch (x) { case 10: return 10; case 1000: return 11; case 100000: return 12; } return 13; }'
+try 3 'int main() { int x=3; switch (x) { case 1: x=5; default: ; } return x; }'
+
+# goto
+try 3 'int main() { int x=1; goto end; x=2; end: x=x+2; return x; }'
+try 5 'int main() { int i=0; loop: i=i+1; if (i-5) goto loop; return i; }'
+try 7 'int main() { goto a; b: return 7; a: goto b; }'
+try 4 'typedef int t; int main() { int x=0; t: x=4; return x; }'
+try 9 'int f() { goto out; out: return 9; } int main() { goto out; out: return f(); }'
+try 6 'int main() { int x=6; goto L; x=1; L: ; return x; }'
+
+# null statement
+try 2 'int main() { ; ;; return 2; }'
+try 4 'int main() { int x=4; if (x) ; else x=1; return x; }'
+
+# bitwise and shift operators
+try 2 'int main() { return 17%5; }'
+try 3 'int main() { return 3&7; }'
+try 7 'int main() { return 3|6-2; }'
+try 5 'int main() { return 3^6; }'
+try 40 'int main() { return 5<<3; }'
+try 6 'int main() { return 100>>4; }'
+try 255 'int main() { return (0-1)>>60; }'
+try 4 'int main() { return 1<<2&6|0; }'
+
+# compound assignment, ++ and --
+try 7 'int main() { int x=3; x+=4; return x; }'
+try 2 'int main() { int x=5; x-=3; return x; }'
+try 12 'int main() { int x=3; x*=4; return x; }'
+try 3 'int main() { int x=13; x/=4; return x; }'
+try 1 'int main() { int x=13; x%=4; return x; }'
+try 24 'int main() { int x=3; x<<=3; return x; }'
+try 3 'int main() { int x=27; x>>=3; return x; }'
+try 2 'int main() { int x=6; x&=3; return x; }'
+try 7 'int main() { int x=6; x|=3; return x; }'
+try 5 'int main() { int x=6; x^=3; return x; }'
+try 9 'int main() { int x=3; return x+=6; }'
+try 128 'int main() { char c=127; c+=1; return c+256; }'
+try 4 'int main() { int a[3]={1,2,4}; int *p=a; p+=2; return *p; }'
+try 2 'int main() { int a[3]={1,2,4}; int *p=a+2; p-=1; return *p; }'
+try 11 'int main() { int a[2]={1,2}; int i=0; a[i++]+=9; return a[0]+i; }'
+try 4 'int main() { int x=3; return ++x; }'
+try 3 'int main() { int x=3; return x++; }'
+try 4 'int main() { int x=3; x++; return x; }'
+try 2 'int main() { int x=3; return --x; }'
+try 3 'int main() { int x=3; return x--; }'
+try 2 'int main() { int x=3; x--; return x; }'
+try 127 'int main() { char c=127; return c++; }'
+try 128 'int main() { char c=127; c++; return c+256; }'
+try 2 'int main() { int a[3]={1,2,4}; int *p=a; p++; return *p; }'
+try 1 'int main() { int a[3]={1,2,4}; int *p=a; return *p++; }'
+try 4 'int main() { int a[3]={1,2,4}; int *p=a+1; return *++p; }'
+try 6 'struct s { int a; int b; }; int main() { struct s x={2,3}; struct s *p=&x; p->a++; ++p->b; return p->a+x.b-1; }'
+
+# comma operator
+try 3 'int main() { return (1, 2, 3); }'
+try 5 'int main() { int x=1; int y=0; y=(x+=1, x*2), y=y+1; return y; }'
+try 2 'int main() { int x=0; x++, x++; return x; }'
+
+# ternary operator
+try 2 'int main() { return 1 ? 2 : 3; }'
+try 3 'int main() { return 0 ? 2 : 3; }'
+try 5 'int main() { int x=0; return x ? 4 : x+5; }'
+try 8 'int main() { int a=0; int b=0; 1 ? a++ : b++; 0 ? a++ : b++; return a*4+b*4; }'
+try 4 'int main() { return 0 ? 1 : 0 ? 3 : 4; }'
+try 3 'int main() { return 1 ? 0 ? 2 : 3 : 4; }'
+try 6 'int main() { int x=1; x += 0 ? 2 : 5; return x; }'
+try 8 'int main() { char c=1; long l=2; return sizeof(c ? c : l); }'
+try 7 'int main() { int a=7; int b=9; int *p=1 ? &a : &b; return *p; }'
+try 12 'int main() { int a[0 ? 2 : 3]; return sizeof(a); }'
+try 1 'int main() { switch (2) { case 1 ? 2 : 3: return 1; } return 0; }'
+try 6 'int g = 1 ? 6 : 7; int main() { return g; }'
+try 5 'int f(int x) { return x ? f(x-1)+1 : 0; } int main() { return f(5); }'
+
+# chained assignment
+try 3 'int main() { int a; int b; a=b=3; return a; }'
+try 6 'int main() { int a; int b; int c; a=b=c=2; return a+b+c; }'
+try 5 'int main() { int x; return x=5; }'
+try 44 'int main() { char c; int x; x=c=300; return x; }'
+try 12 'int main() { int a=1; int b=2; a+=b*=3; return a+b-1; }'
+try 3 'int main() { int a[2]; int i=0; a[i=i+1]=3; return a[i]; }'
+try 7 'int main() { int x; int *p=&x; *p=x=7; return x; }'
+
+# preprocessor
+try 3 $'#define X 3\nint main() { return X; }'
+try 7 $'#define ADD(a, b) ((a)+(b))\nint main() { return ADD(3, 4); }'
+try 9 $'#define SQ(x) ((x)*(x))\nint main() { return SQ(1+2); }'
+try 6 $'#define S(x) #x\nint main() { return sizeof(S(a + b)); }'
+try 99 $'#define S(x) #x\nint main() { return S(abc)[2]; }'
+try 12 $'#define CAT(a, b) a##b\nint main() { int xy=12; return CAT(x, y); }'
+try 34 $'#define CAT(a, b) a ## b\nint main() { return CAT(3, 4); }'
+try 5 $'#define CAT(a, b) a ## b\nint main() { return CAT(, 5); }'
+try 2 $'#define f(x) x+1\n#define g f\nint main() { return g(1); }'
+try 4 $'int main() { int x=2;\n#define x x+2\nreturn x; }'
+try 6 $'int main() { int A=3;\n#define A B+1\n#define B A+2\nreturn A; }'
+try 3 $'#define X 3\n#undef X\nint main() { int X=3; return X; }'
+try 4 $'#define f(x) x\nint main() { int f=4; return f; }'
+try 1 $'#define X 1\n#ifdef X\nint main() { return 1; }\n#else\nint main() { return 2; }\n#endif'
+try 2 $'#ifdef X\nint main() { return 1; }\n#else\nint main() { return 2; }\n#endif'
+try 2 $'#ifndef X\n#define X 2\n#endif\nint main() { return X; }'
+try 3 $'#define V 3\n#if V == 1\nint main() { return 1; }\n#elif V == 2\nint main() { return 2; }\n#elif V == 3\nint main() { return 3; }\n#else\nint main() { return 4; }\n#endif'
+try 4 $'#if 1 > 2 || !defined(V) && (3 << 1) % 4 == 2 ? 1 : 0\nint main() { return 4; }\n#endif'
+try 5 $'#if 0\n#if 1\nint main() { return 1; }\n#endif\n#else\nint main() { return 5; }\n#endif'
+try 6 $'#if defined X || UNDEFINED\nint main() { return 1; }\n#else\nint main() { return 6; }\n#endif'
+try 2 $'int main() {\n  return __LINE__;\n}'
+try 44 $'int main() {\n#line 42\n  return __LINE__ + 2;\n}'
+try 102 $'#line 1 "foo.c"\nint main() { return __FILE__[0]; }'
+try 7 $'#define LONG_MACRO(a) \\\n  (a * 7)\nint main() { return LONG_MACRO(1); }'
+try 3 $'#\nint main() { return 3; }'
+echo '#define INC_A 5' > tmp-inc1.h
+echo $'#include "tmp-inc1.h"\nint inc_b() { return INC_A + 1; }' > tmp-inc2.h
+try 11 $'#include "tmp-inc2.h"\nint main() { return inc_b() + INC_A; }'
+mkdir -p tmp-dir && echo 'int dir_x = 8;' > tmp-dir/tmp-inc3.h
+echo $'#ifndef GUARD\n#define GUARD\nint guarded = 3;\n#endif' > tmp-dir/tmp-inc4.h
+try_args() {
+  expected="$1"
+  shift
+  ./target/x86_64-unknown-linux-musl/debug/mona "$@" > tmp.s
+  gcc -static -o tmp tmp.s
+  ./tmp
+  actual="$?"
+
+  if [ "$actual" == "$expected" ]; then
+    echo "$* => $actual"
+  else
+    echo "$* => $expected expected, but got $actual"
+    exit 1
+  fi
+}
+try_args 8 -Itmp-dir $'#include <tmp-inc3.h>\nint main() { return dir_x; }'
+try_args 3 -I tmp-dir $'#include <tmp-inc4.h>\n#include <tmp-inc4.h>\nint main() { return guarded; }'
+try_args 8 -Itmp-dir $'#define H <tmp-inc3.h>\n#include H\nint main() { return dir_x; }'
+
+# comments
+try 2 'int main() { /* return 1; */ return 2; }'
+try 3 $'int main() {\n  // return 1;\n  return 3; // trailing\n}'
+try 4 $'int main() {\n  /* a\n     multi-line\n     comment */\n  return 4;\n}'
+try 5 'int main() { return 10/*x*//2; }'
+try 6 'int main() { return 3 /* ** / */ * 2; }'
+try 7 $'// leading comment\nint main() { return \'/\' - 40; }'
+try 8 $'#define X 8 // comment\nint main() { return X; }'
+try 9 $'#define Y /* a */ 9\nint main() { return Y; }'
+try 4 $'int main() {\n/* */ return __LINE__ + 2;\n}'
+
+# relational, equality and logical operators
+try 1 'int main() { return 1<2; }'
+try 0 'int main() { return 2<1; }'
+try 1 'int main() { return 2<=2; }'
+try 0 'int main() { return 3<=2; }'
+try 1 'int main() { return 3>2; }'
+try 0 'int main() { return 2>2; }'
+try 1 'int main() { return 2>=2; }'
+try 0 'int main() { return 1>=2; }'
+try 1 'int main() { return 5==5; }'
+try 0 'int main() { return 5!=5; }'
+try 1 'int main() { return -1<0; }'
+try 1 'int main() { long a=-5; int b=3; return a<b; }'
+try 1 'int main() { int a[2]; return &a[0]<&a[1]; }'
+try 1 'int main() { int x; int *p=&x; return p==&x; }'
+try 1 'int main() { return 2&&3; }'
+try 0 'int main() { return 2&&0; }'
+try 1 'int main() { return 0||5; }'
+try 0 'int main() { return 0||0; }'
+try 3 'int main() { int a=3; 0&&(a=5); return a; }'
+try 3 'int main() { int a=3; 1||(a=5); return a; }'
+try 5 'int main() { int a=3; 1&&(a=5); return a; }'
+try 5 'int main() { int a=3; 0||(a=5); return a; }'
+try 1 'int main() { int i=0; int j=0; return (i++>j)==0 && i==1; }'
+
+# unary operators
+try 3 'int main() { return -(-3); }'
+try 7 'int main() { return 10+-3; }'
+try 5 'int main() { return +5; }'
+try 1 'int main() { return !0; }'
+try 0 'int main() { return !7; }'
+try 1 'int main() { int *p=0; return !p; }'
+try 250 'int main() { return ~5 & 255; }'
+try 1 'int main() { return ~-1 == 0; }'
+try 2 'int main() { enum { A = -1, B = A + 3 }; return B; }'
+try 3 'int main() { switch (-2) { case -2: return 3; } return 0; }'
+
+# precedence and associativity
+try 7 'int main() { return 1 + 2 * 3; }'
+try 1 'int main() { return 7 - 4 - 2; }'
+try 2 'int main() { return 16 / 4 / 2; }'
+try 24 'int main() { return 1 + 2 << 3; }'
+try 1 'int main() { return 1 << 2 < 5; }'
+try 1 'int main() { return 2 < 1 == 0; }'
+try 1 'int main() { return 3 & 1 == 1; }'
+try 5 'int main() { return 1 | 2 ^ 3 & 6 | 4; }'
+try 1 'int main() { return 0 && 1 || 1; }'
+try 0 'int main() { return 1 || 0 && 0 ? 0 : 1; }'
+try 3 'int main() { return 0 ? 1 : 0 ? 2 : 3; }'
+try 5 'int main() { int a; int b; a = b = 0 || 5 > 4 ? 5 : 6; return a; }'
+try 2 'int main() { int a = 1; a += 1 == 1; return a; }'
+try 6 'int main() { int a; return (a = 1, a + 5); }'
+try 1 'int main() { return sizeof -1 == 4; }'
+try 1 'int main() { return !1 + 1; }'
+
+# AST dumps
+try_dump() {
+  expected="$1"
+  shift
+  actual=$(./target/x86_64-unknown-linux-musl/debug/mona "$@")
+
+  if [ "$actual" == "$expected" ]; then
+    echo "$* => ok"
+  else
+    echo "$* => expected:"
+    echo "$expected"
+    echo "but got:"
+    echo "$actual"
+    exit 1
+  fi
+}
+
+try_dump 'Func main int [0..26]
+  CompStmt [11..26]
+    Return [13..24]
+      BinOp + [20..23]
+        Num 1 [20..21]
+        Num 2 [22..23]' -dump-ast 'int main() { return 1+2; }'
+try_dump 'Vardef g int[2][3] [0..12]
+Func f int [13..55]
+  Vardef p char* [19..26]
+  CompStmt [28..55]
+    ExprStmt [30..43]
+      BinOp = [30..42]
+        Index [30..34]
+          Ident p [30..31]
+          Num 1 [32..33]
+        Str "a\"" [37..42]
+    Return [44..53]
+      Ident x [51..52]' -dump-ast 'int g[2][3]; int f(char *p) { p[1] = "a\""; return x; }'
+try_dump '[{"kind":"Func","name":"main","type":"int","span":[0,25],"children":[{"kind":"CompStmt","span":[11,25],"children":[{"kind":"Return","span":[13,23],"children":[{"kind":"Unary","op":"-","span":[20,22],"children":[{"kind":"Num","value":1,"span":[21,22],"children":[]}]}]}]}]}]' -dump-ast=json 'int main() { return -1; }'
+
+# formatting
+fmt1='int add(int a, int b) {
+  return a + b;
+}
+
+int main() {
+  int x = 1 + 2 * 3;
+  if (x > 1) {
+    x = - -x;
+  } else if (x)
+    x++;
+  else {
+    --x;
+  }
+  return add((x, 1), sizeof(int *)); // done
+}'
+try_dump "$fmt1" fmt 'int add(int a,int b){return a+b;}
+int main(){int x=1+2*3;if(x>1){x=-(-x);}else if(x)x++;else{--x;}
+return add((x,1),sizeof(int*));  // done
+}'
+try_dump "$fmt1" fmt "$fmt1"
+
+fmt2='#define N 3
+// colors
+enum Color {
+  RED,
+  GREEN = 2
+};
+struct P {
+  int x; // x coord
+
+  int y; /* end of P */
+};
+
+int main() {
+  switch (GREEN) {
+  case 2:
+    return (1 ? 2 : 3) ? 0x1F : '"'a'"';
+  default:
+    break;
+  }
+L:
+  goto L;
+}'
+try_dump "$fmt2" fmt '#define N 3
+// colors
+enum Color { RED, GREEN=2 };
+struct P { int x;   // x coord
+
+int y; /* end of P */ };
+int main() { switch (GREEN) { case 2: return (1?2:3)?0x1F:'"'a'"'; default: break; } L: goto L; }'
+try_dump "$fmt2" fmt "$fmt2"
+
+fmt3='int main() {
+  ;
+  if (1)
+    ;
+  switch (1) {
+  default:
+    ;
+  }
+L:
+  ;
+  return 0;
+}'
+try_dump "$fmt3" fmt 'int main() { ; if (1) ; switch (1) { default: ; } L: ; return 0; }'
+try_dump "$fmt3" fmt "$fmt3"
+
+# textual IR
+try_ir() {
+  expected="$1"
+  printf '%s\n' "$2" > tmp.ir
+
+  ./target/x86_64-unknown-linux-musl/debug/mona --from-ir tmp.ir > tmp.s
+  gcc -static -o tmp tmp.s
+  ./tmp
+  actual="$?"
+
+  if [ "$actual" == "$expected" ]; then
+    echo "$2 => $actual"
+  else
+    echo "$2 => $expected expected, but got $actual"
+    exit 1
+  fi
+}
+
+# Compiles code through its IR dump.
+try_roundtrip() {
+  expected="$1"
+  input="$2"
+  ./target/x86_64-unknown-linux-musl/debug/mona -dump-ir1 "$input" > tmp.ir
+  try_ir "$expected" "$(cat tmp.ir)"
+}
+
+try_ir 42 'main():
+  IMM r1, 40
+  IMM r2, 2
+  ADD r3, r1, r2
+  KILL r1
+  KILL r2
+  RET r3
+  KILL r3'
+try_ir 7 '; the answer is 7
+main():
+  IMM r1, 0
+  UNLESS r1, .L0
+  IMM r1, 3
+.L0:
+  IMM r2, -7
+  IMM r3, 0
+  SUB r2, r3, r2  ; 0 - -7
+  KILL r3
+  RET r2
+  KILL r2'
+try_ir 98 '.rodata msg, 3, 1
+  .byte 97, 98, 0
+.data p, 8, 8
+  .quad msg
+
+main():
+  LEA r1, p
+  LOAD64 r1, r1
+  ADD_IMM r1, r1, 1
+  LOAD8 r1, r1
+  RET r1
+  KILL r1'
+try_ir 5 'two():
+  IMM r1, 2
+  RET r1
+  KILL r1
+
+main():
+  CALL r1, two()
+  IMM r2, 3
+  ADD r1, r1, r2
+  KILL r2
+  RET r1
+  KILL r1'
+try_roundtrip 3 'int g=3; int *p=&g; int main() { return *p; }'
+try_roundtrip 6 'int main() { int x=2; switch (x) { case 1: case 2: case 3: case 4: x=x*3; } return x; }'
+try_roundtrip 98 'int sub(int a, int b) { return a-b; } int main() { char *s="abc"; return s[sub(3, 2)]; }'
+
+fail_ir() {
+  expected="$1"
+  printf '%s\n' "$2" > tmp.ir
+
+  if output=$(./target/x86_64-unknown-linux-musl/debug/mona -verify-ir --from-ir tmp.ir 2>&1); then
+    echo "$2 => error expected, but it compiled"
+    exit 1
+  fi
+
+  if [[ "$output" == *"$expected"* ]]; then
+    echo "$2 => $expected"
+  else
+    echo "$2 => $expected expected, but got $output"
+    exit 1
+  fi
+}
+
+fail_ir 'unknown instruction FOO at line 2' $'main():\n  FOO r1'
+fail_ir 'register expected, but got 3 at line 2' $'main():\n  ADD r1, r1, 3'
+fail_ir 'argument slot 7 out of range at line 3' $'main():\n  IMM r1, 0\n  STORE_ARG32 r1, 7\n  RET r1\n  KILL r1'
+fail_ir 'instruction outside of a function at line 1' '  RET r1'
+fail_ir 'g has 2 bytes of data, but its size is 4 at line 1' $'.data g, 4, 4\n  .byte 1, 2\n\nmain():\n  IMM r1, 0\n  RET r1'
+fail_ir 'p has 8 bytes of data, but its size is 4 at line 2' $'.bss g, 4, 4\n.data p, 4, 4\n  .quad g\n\nmain():\n  IMM r1, 0\n  RET r1'
+fail_ir 'label .L0 defined twice at line 7' $'f():\n.L0:\n  IMM r1, 0\n  RET r1\n\nmain():\n.L0:\n  IMM r1, 0\n  RET r1'
+fail_ir 'r2 used before it is defined at instruction 1 of main' $'main():\n  IMM r1, 1\n  ADD r1, r1, r2\n  RET r1'
+fail_ir 'r1 killed twice at instruction 3 of main' $'main():\n  IMM r1, 0\n  RET r1\n  KILL r1\n  KILL r1'
+fail_ir 'r1 used after it is killed' $'f():\n  IMM r1, 0\n  RET r1\n\nmain():\n  IMM r1, 1\n  CALL r2, f(r1)\n  RET r1'
+fail_ir 'jump to undefined label .L3' $'main():\n  JMP .L3\n  IMM r1, 0\n  RET r1'
+fail_ir 'function does not end in a return' $'main():\n  IMM r1, 0\n  KILL r1'
+
+# errors
+fail 'expected ; but got end of input' 'int main() { return 1'
+fail 'integer literal is too large: 9223372036854775808' 'int main() { return 9223372036854775808; }'
+fail 'integer literal is too large: 0x10000000000000000' 'int main() { return 0x10000000000000000; }'
+fail 'expected ) but got end of input' 'int main() { return (1'
+fail 'number expected, but got end of input' 'int main() { return'
+fail 'expected } but got end of input' 'int main() { struct { int a; } x; return 0;'
+fail 'expected ; but got } at line 2' $'int main() {\n  int a = 3 }'
+fail 'identifier expected, but got 3' 'int main() { int 3; }'
+fail 'expression is not assignable at line 1' 'int main() { 1 = 2; }'
+fail 'expression is not assignable at line 1' 'int main() { int a; int b; a + b = 1; }'
+fail 'expression is not assignable at line 1' 'int main() { int a; int b; 1 ? a : b = 2; }'
+fail 'expression is not assignable at line 1' 'int a[2]; int b[2]; a = b;'
+fail 'expression is not assignable at line 2' 'int main() { int a[2];
+a += 1; }'
+
 echo OK
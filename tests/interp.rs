@@ -0,0 +1,174 @@
+// Runs the programs in test.sh on the IR interpreter instead of an
+// assembler: each one on the virtual registers out of gen_ir, after
+// alloc_regs, and after lowering to two-address form. All runs must return
+// what test.sh expects, so a wrong register allocation or lowering shows
+// up as a difference between them.
+//
+// test.sh stays the one list of test cases. This reads the `try`,
+// `try_args`, `try_ir` and `try_roundtrip` lines from it, and runs its
+// `echo` and `mkdir` lines to create the headers the include tests use.
+
+use std::{env, fs};
+
+use mona::codegen::lower_two_address;
+use mona::interp::run;
+use mona::ir::{gen_ir, ir_text, Function};
+use mona::irparse::parse_ir;
+use mona::parse::Node;
+use mona::preprocess::add_include_path;
+use mona::regalloc::alloc_regs;
+use mona::sema::{sema, Global};
+use mona::token::tokenize;
+use mona::verify::{verify, verify_allocated};
+
+// Splits a shell script into commands, and commands into words. Handles
+// the quoting test.sh uses: '...', "..." and $'...'.
+fn commands(script: &str) -> Vec<Vec<String>> {
+    let mut cmds = vec![];
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = script.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' | ' ' | '\t' | ';' => {
+                words.extend(word.take());
+                if c == '\n' || c == ';' {
+                    cmds.push(std::mem::take(&mut words));
+                }
+            }
+            '#' if word.is_none() => while chars.next_if(|c| *c != '\n').is_some() {},
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                w.extend(chars.by_ref().take_while(|c| *c != '\''));
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let w = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\'' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => w.push('\n'),
+                            Some('t') => w.push('\t'),
+                            Some(c) => w.push(c),
+                            None => (),
+                        },
+                        c => w.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) => {
+                            w.push(chars.next().unwrap())
+                        }
+                        c => w.push(c),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    cmds.push(words);
+    cmds.retain(|words| !words.is_empty());
+    cmds
+}
+
+fn compile(code: &str) -> (Vec<Global>, Vec<Function>) {
+    let tokens = tokenize(code.to_string(), "-");
+    let nodes = Node::parse(&tokens);
+    let prog = sema(nodes);
+    (prog.globals, gen_ir(prog.funcs))
+}
+
+// `pass` names what made the IR, for the verifier.
+fn check(expected: &str, src: &str, pass: &str, globals: Vec<Global>, mut fns: Vec<Function>) {
+    println!("{}", src);
+    let expected: i64 = expected.parse().unwrap();
+    verify(&fns, pass);
+
+    let before = run(&globals, &fns);
+    assert_eq!(before & 0xff, expected, "{}", src);
+
+    for f in fns.iter_mut() {
+        alloc_regs(&mut f.irv);
+    }
+    verify_allocated(&fns, "alloc_regs");
+    let after = run(&globals, &fns);
+    assert_eq!(
+        after, before,
+        "register allocation changed the result of {}",
+        src
+    );
+
+    for f in fns.iter_mut() {
+        lower_two_address(&mut f.irv);
+    }
+    verify_allocated(&fns, "lower_two_address");
+    let lowered = run(&globals, &fns);
+    assert_eq!(
+        lowered, before,
+        "lowering to two-address form changed the result of {}",
+        src
+    );
+}
+
+// The compiler keeps its state in globals, so everything runs in one test.
+#[test]
+fn test_sh() {
+    let script = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/test.sh")).unwrap();
+    env::set_current_dir(env!("CARGO_TARGET_TMPDIR")).unwrap();
+
+    let mut count = 0;
+    for cmd in commands(&script) {
+        for words in cmd.split(|w| w == "&&") {
+            // Calls within the helpers themselves pass on "$expected".
+            if words.len() > 1 && words[0].starts_with("try") && words[1].parse::<i64>().is_err() {
+                continue;
+            }
+            match words {
+                [echo, text, gt, path] if echo == "echo" && gt == ">" => {
+                    fs::write(path, format!("{}\n", text)).unwrap();
+                }
+                [mkdir, p, dir] if mkdir == "mkdir" && p == "-p" => {
+                    fs::create_dir_all(dir).unwrap();
+                }
+                [try_, expected, code] if try_ == "try" => {
+                    let (globals, fns) = compile(code);
+                    check(expected, code, "gen_ir", globals, fns);
+                }
+                [try_, expected, args @ .., code] if try_ == "try_args" => {
+                    let mut args = args.iter();
+                    while let Some(arg) = args.next() {
+                        match arg.strip_prefix("-I") {
+                            Some("") => add_include_path(args.next().unwrap().clone()),
+                            Some(dir) => add_include_path(dir.to_string()),
+                            None => panic!("unknown argument {}", arg),
+                        }
+                    }
+                    let (globals, fns) = compile(code);
+                    check(expected, code, "gen_ir", globals, fns);
+                }
+                [try_, expected, text] if try_ == "try_ir" => {
+                    let (globals, fns) = parse_ir(text);
+                    check(expected, text, "parse_ir", globals, fns);
+                }
+                [try_, expected, code] if try_ == "try_roundtrip" => {
+                    let (globals, fns) = compile(code);
+                    let (globals, fns) = parse_ir(&ir_text(&globals, &fns));
+                    check(expected, code, "gen_ir", globals, fns);
+                }
+                _ => continue,
+            }
+            count += 1;
+        }
+    }
+
+    // A change in how test.sh is written should not quietly skip it.
+    assert!(count > 300, "only {} commands of test.sh ran", count);
+}